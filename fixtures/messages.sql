@@ -1,7 +1,7 @@
-INSERT INTO messages(id, role, content, conversation_id, created_at)
+INSERT INTO messages(id, role, content, conversation_id, parent_id, created_at)
 VALUES
-    (1, 'user', 'why is the sky blue?', 1, '2024-09-13 09:00:00'),
-    (2, 'assistant', 'I don''t know', 1, '2024-09-13 09:00:59'),
-    (3, 'user', 'tell me a joke', 2, '2024-09-13 09:01:00'),
-    (4, 'assistant', 'I don''t know any jokes', 2, '2024-09-13 09:01:00'),
-    (5, 'user', 'what''s the capital of Poland?', 2, '2024-09-13 09:01:00');
+    (1, 'user', 'why is the sky blue?', 1, NULL, '2024-09-13 09:00:00'),
+    (2, 'assistant', 'I don''t know', 1, 1, '2024-09-13 09:00:59'),
+    (3, 'user', 'tell me a joke', 2, NULL, '2024-09-13 09:01:00'),
+    (4, 'assistant', 'I don''t know any jokes', 2, 3, '2024-09-13 09:01:00'),
+    (5, 'user', 'what''s the capital of Poland?', 2, 4, '2024-09-13 09:01:00');
@@ -0,0 +1,5 @@
+ALTER TABLE conversations DROP COLUMN seed;
+ALTER TABLE conversations DROP COLUMN max_tokens;
+ALTER TABLE conversations DROP COLUMN top_p;
+ALTER TABLE conversations DROP COLUMN temperature;
+ALTER TABLE conversations DROP COLUMN model;
@@ -0,0 +1,5 @@
+ALTER TABLE conversations ADD COLUMN model TEXT;
+ALTER TABLE conversations ADD COLUMN temperature REAL;
+ALTER TABLE conversations ADD COLUMN top_p REAL;
+ALTER TABLE conversations ADD COLUMN max_tokens INTEGER;
+ALTER TABLE conversations ADD COLUMN seed INTEGER;
@@ -0,0 +1,5 @@
+DROP TRIGGER messages_active_branch;
+DROP INDEX idx_messages_parent_id;
+
+ALTER TABLE conversations DROP COLUMN active_message_id;
+ALTER TABLE messages DROP COLUMN parent_id;
@@ -0,0 +1,36 @@
+ALTER TABLE messages ADD COLUMN parent_id INTEGER;
+ALTER TABLE conversations ADD COLUMN active_message_id INTEGER;
+
+-- existing conversations are linear, every message continues the previous one
+UPDATE messages
+SET parent_id = (
+    SELECT p.id
+    FROM messages p
+    WHERE p.conversation_id = messages.conversation_id
+        AND p.role != 'system'
+        AND (p.created_at, p.id) < (messages.created_at, messages.id)
+    ORDER BY p.created_at DESC, p.id DESC
+    LIMIT 1
+)
+WHERE role != 'system';
+
+UPDATE conversations
+SET active_message_id = (
+    SELECT m.id
+    FROM messages m
+    WHERE m.conversation_id = conversations.id
+        AND m.role != 'system'
+    ORDER BY m.created_at DESC, m.id DESC
+    LIMIT 1
+);
+
+CREATE INDEX idx_messages_parent_id ON messages(parent_id);
+
+-- new message becomes the leaf of conversation's active branch
+CREATE TRIGGER messages_active_branch AFTER INSERT ON messages
+WHEN NEW.role != 'system'
+BEGIN
+    UPDATE conversations
+    SET active_message_id = NEW.id
+    WHERE id = NEW.conversation_id;
+END;
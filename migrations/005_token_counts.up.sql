@@ -0,0 +1,2 @@
+-- counted lazily once message is sent to the model, tokenizer depends on the backend
+ALTER TABLE messages ADD COLUMN token_count INTEGER;
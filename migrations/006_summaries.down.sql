@@ -0,0 +1,2 @@
+DELETE FROM messages WHERE summary;
+ALTER TABLE messages DROP COLUMN summary;
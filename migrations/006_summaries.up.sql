@@ -0,0 +1,3 @@
+-- summaries are system messages that follow up the last message they cover,
+-- they're not part of the conversation's path, only fed to the model instead of covered messages
+ALTER TABLE messages ADD COLUMN summary BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+DROP TRIGGER message_embeddings_update;
+DROP TABLE message_embeddings;
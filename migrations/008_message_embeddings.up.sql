@@ -0,0 +1,13 @@
+-- vectors are stored as little-endian f32 values, model tells which embedder produced them
+CREATE TABLE IF NOT EXISTS message_embeddings (
+    message_id INTEGER PRIMARY KEY,
+    model TEXT NOT NULL,
+    embedding BLOB NOT NULL,
+    FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+-- edited content has to be embedded again
+CREATE TRIGGER message_embeddings_update AFTER UPDATE OF content ON messages
+BEGIN
+    DELETE FROM message_embeddings WHERE message_id = NEW.id;
+END;
@@ -0,0 +1,2 @@
+-- deleted conversations are kept in trash until they're restored or purged
+ALTER TABLE conversations ADD COLUMN deleted_at TIMESTAMP;
@@ -1,16 +1,26 @@
+use std::sync::Arc;
+
+use chrono::Utc;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use kalosm::language::Llama;
 use sqlx::SqlitePool;
 use tokio::sync::mpsc::{self, Sender, UnboundedSender};
 
 use crate::{
     assistant::Assistant,
-    chat::Chat,
-    conversations::{Conversations, DeleteConversationPopup, NewConversationPopup},
+    backend::{Cancellation, InferenceBackend, ModelInfo},
+    chat::{Chat, EditMessagePopup, FindBar},
+    context::ContextWindow,
+    conversations::{
+        remove_session_file, ConversationSettingsPopup, Conversations, DeleteConversationPopup,
+        ExportConversationPopup, NewConversationPopup, RenameConversationPopup,
+    },
     db,
+    embeddings::{self, Embedder, RELATED_CONVERSATIONS_LIMIT},
     event::{Event, InferenceType},
+    export,
     models::{Message, Role},
     prompt::Prompt,
+    search::{RelatedConversationsPopup, SearchPopup, SEARCH_LIMIT},
     AppResult, CONFIG,
 };
 
@@ -53,35 +63,86 @@ pub struct App {
     // TODO: I cannot allow empty conversation
     pub new_conversation_popup: NewConversationPopup,
     pub delete_conversation_popup: DeleteConversationPopup,
+    pub conversation_settings_popup: ConversationSettingsPopup,
+    pub edit_message_popup: EditMessagePopup,
+    pub rename_conversation_popup: RenameConversationPopup,
+    pub export_conversation_popup: ExportConversationPopup,
+    pub search_popup: SearchPopup,
+    pub find_bar: FindBar,
+    pub related_conversations_popup: RelatedConversationsPopup,
     focus: AppFocus,
+    model_info: ModelInfo,
     event_tx: UnboundedSender<Event>,
     inference_tx: Sender<Message>,
+    inference_cancellation: Arc<Cancellation>,
+    backend: Arc<dyn InferenceBackend>,
+    /// Finds related conversations, it's available when semantic search is enabled
+    embedder: Option<Arc<dyn Embedder>>,
     running: bool,
     sqlite: SqlitePool,
 }
 
 impl App {
-    pub fn new(sqlite: SqlitePool, event_tx: UnboundedSender<Event>, llama: Llama) -> Self {
+    pub fn new(
+        sqlite: SqlitePool,
+        event_tx: UnboundedSender<Event>,
+        backend: Arc<dyn InferenceBackend>,
+        context_window: ContextWindow,
+    ) -> Self {
         let (inference_tx, inference_rx) = mpsc::channel::<Message>(10);
-        Assistant::run(llama, sqlite.clone(), inference_rx, event_tx.clone());
+        let model_info = backend.model_info();
+        let inference_cancellation = Arc::new(Cancellation::default());
+        Assistant::run(
+            backend.clone(),
+            sqlite.clone(),
+            inference_rx,
+            event_tx.clone(),
+            inference_cancellation.clone(),
+            context_window,
+        );
 
         Self {
-            chat: Chat::new(sqlite.clone()),
+            chat: Chat::new(sqlite.clone()).with_context_window(context_window),
             conversations: Conversations::new(sqlite.clone()),
             prompt: Default::default(),
             new_conversation_popup: Default::default(),
             delete_conversation_popup: Default::default(),
+            conversation_settings_popup: Default::default(),
+            edit_message_popup: Default::default(),
+            rename_conversation_popup: Default::default(),
+            export_conversation_popup: Default::default(),
+            search_popup: Default::default(),
+            find_bar: Default::default(),
+            related_conversations_popup: Default::default(),
             focus: Default::default(),
+            model_info,
             event_tx,
             inference_tx,
+            inference_cancellation,
+            backend,
+            embedder: None,
             running: true,
             sqlite,
         }
     }
 
+    pub fn with_embedder(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+
     pub async fn init(&mut self) -> AppResult<()> {
+        let trash_retention = CONFIG.read().await.trash_retention();
+        for conversation in db::purge_trash(&self.sqlite, Utc::now() - trash_retention).await? {
+            tracing::info!("purging conversation from trash: {:?}", conversation.id);
+            remove_session_file(&conversation);
+            self.backend.forget(conversation).await;
+        }
+
         let conversations = db::get_conversations(&self.sqlite).await?;
         self.conversations.set_conversations(conversations);
+        let trashed = db::get_trashed_conversations(&self.sqlite).await?;
+        self.conversations.set_trashed(trashed);
 
         Ok(())
     }
@@ -90,6 +151,10 @@ impl App {
         self.running
     }
 
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.model_info
+    }
+
     pub fn current_focus(&self) -> AppFocus {
         self.focus
     }
@@ -103,6 +168,42 @@ impl App {
     }
 
     pub async fn handle_key_events(&mut self, key_event: KeyEvent) -> AppResult<()> {
+        if self.conversation_settings_popup.is_activated() {
+            return self
+                .handle_conversation_settings_popup_key_events(key_event)
+                .await;
+        }
+
+        if self.edit_message_popup.is_activated() {
+            return self.handle_edit_message_popup_key_events(key_event).await;
+        }
+
+        if self.rename_conversation_popup.is_activated() {
+            return self
+                .handle_rename_conversation_popup_key_events(key_event)
+                .await;
+        }
+
+        if self.export_conversation_popup.is_activated() {
+            return self
+                .handle_export_conversation_popup_key_events(key_event)
+                .await;
+        }
+
+        if self.search_popup.is_activated() {
+            return self.handle_search_popup_key_events(key_event).await;
+        }
+
+        if self.related_conversations_popup.is_activated() {
+            return self
+                .handle_related_conversations_popup_key_events(key_event)
+                .await;
+        }
+
+        if self.find_bar.is_activated() {
+            return self.handle_find_bar_key_events(key_event);
+        }
+
         match key_event.code {
             // Ctrl + c -> exit
             KeyCode::Char('c') | KeyCode::Char('C') => {
@@ -142,24 +243,176 @@ impl App {
                     return Ok(());
                 }
 
-                if let AppFocus::Prompt = self.current_focus() {
-                    self.prompt.handle_input(key_event);
+                match self.current_focus() {
+                    AppFocus::Messages if key_event.code == KeyCode::Char('N') => {
+                        self.chat.previous_match()
+                    }
+                    AppFocus::Messages => self.chat.next_match(),
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    AppFocus::Conversation => {}
                 }
             }
-            KeyCode::Char('y') | KeyCode::Char('Y') => {
-                if self.delete_conversation_popup.is_activated() {
+            KeyCode::Char('/') => {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.handle_input(key_event);
+
+                    return Ok(());
+                }
+
+                match self.current_focus() {
+                    AppFocus::Messages => {
+                        self.find_bar.clear();
+                        self.find_bar.activate();
+                        self.chat.find(None);
+                    }
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    AppFocus::Conversation => {}
+                }
+            }
+            // Ctrl + s
+            KeyCode::Char('s') | KeyCode::Char('S') => {
+                if key_event.modifiers == KeyModifiers::CONTROL {
+                    // conversations in trash are read-only
+                    if self.conversations.is_showing_trash() {
+                        return Ok(());
+                    }
                     if let Some(conversation) = self.conversations.currently_selected() {
-                        db::delete_conversation(&self.sqlite, conversation.id).await?;
-                        self.conversations.delete_conversation(conversation);
+                        let system_prompt =
+                            db::get_system_prompt(&self.sqlite, conversation.id).await?;
+                        self.new_conversation_popup.deactivate();
+                        self.delete_conversation_popup.deactivate();
+                        self.conversation_settings_popup.activate(
+                            &conversation.settings,
+                            system_prompt
+                                .as_ref()
+                                .map(|message| message.content.as_str()),
+                        );
+                    }
+
+                    return Ok(());
+                }
+
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.handle_input(key_event);
+
+                    return Ok(());
+                }
+
+                match self.current_focus() {
+                    // conversations in trash are read-only
+                    AppFocus::Messages if self.conversations.is_showing_trash() => {}
+                    AppFocus::Messages => self.chat.toggle_summary(),
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    AppFocus::Conversation => {}
+                }
+            }
+            // Ctrl + r
+            KeyCode::Char('r') | KeyCode::Char('R')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.show_related_conversations().await?;
+            }
+            // Ctrl + f
+            KeyCode::Char('f') | KeyCode::Char('F')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.new_conversation_popup.deactivate();
+                self.delete_conversation_popup.deactivate();
+                self.search_popup.activate();
+            }
+            KeyCode::Char('e') | KeyCode::Char('E') => {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.handle_input(key_event);
+
+                    return Ok(());
+                }
+
+                match self.current_focus() {
+                    AppFocus::Messages if self.conversations.is_showing_trash() => {}
+                    AppFocus::Messages => {
+                        if self.chat.selected_message().is_none() {
+                            self.chat.select_last_user_message();
+                        }
+                        if let Some(message) = self
+                            .chat
+                            .selected_message()
+                            .filter(|message| message.role == Role::User)
+                        {
+                            self.edit_message_popup.activate(message);
+                        }
+                    }
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    AppFocus::Conversation => {
+                        if let Some(conversation) = self.conversations.currently_selected() {
+                            let exports_dir = CONFIG.read().await.exports_dir();
+                            self.delete_conversation_popup.deactivate();
+                            self.export_conversation_popup
+                                .activate(&conversation, exports_dir);
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('r') | KeyCode::Char('R') => {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.handle_input(key_event);
+
+                    return Ok(());
+                }
+
+                match self.current_focus() {
+                    AppFocus::Messages => self.regenerate_last_response().await?,
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    // conversations in trash are read-only
+                    AppFocus::Conversation if self.conversations.is_showing_trash() => {}
+                    AppFocus::Conversation => {
+                        if let Some(conversation) = self.conversations.currently_selected() {
+                            self.delete_conversation_popup.deactivate();
+                            self.rename_conversation_popup.activate(&conversation);
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('t') | KeyCode::Char('T') => {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.handle_input(key_event);
+
+                    return Ok(());
+                }
+
+                match self.current_focus() {
+                    AppFocus::Conversation => {
+                        self.delete_conversation_popup.deactivate();
+                        self.conversations.toggle_trash();
                         self.chat.reset();
                     }
-                    // we deactivate anyway, if there was conversation it's gone now
-                    // if there wasn't any, no need to keep popup anymore
-                    self.delete_conversation_popup.deactivate();
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    AppFocus::Messages => {}
+                }
+            }
+            KeyCode::Char('u') | KeyCode::Char('U') => {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.handle_input(key_event);
 
                     return Ok(());
                 }
 
+                match self.current_focus() {
+                    AppFocus::Conversation => {
+                        self.delete_conversation_popup.deactivate();
+                        self.restore_conversation().await?;
+                    }
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    AppFocus::Messages => {}
+                }
+            }
+            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                if self.delete_conversation_popup.is_activated() {
+                    // we deactivate anyway, if there was conversation it's in trash now (or gone
+                    // for good when it was deleted from trash view), if there wasn't any, no need
+                    // to keep popup anymore
+                    return self.delete_selected_conversation().await;
+                }
+
                 if self.new_conversation_popup.is_activated() {
                     self.new_conversation_popup.handle_input(key_event);
 
@@ -183,9 +436,23 @@ impl App {
                                 .expect("cannot convert to path to string")
                                 .to_string()
                         };
-                        let new_conversation =
-                            db::create_conversation(&self.sqlite, conversation_name, &session_path)
-                                .await?;
+                        let mut transaction = self.sqlite.begin().await?;
+                        let new_conversation = db::create_conversation(
+                            &mut *transaction,
+                            conversation_name,
+                            &session_path,
+                        )
+                        .await?;
+                        if let Some(system_prompt) = self.new_conversation_popup.get_system_prompt()
+                        {
+                            db::create_system_prompt(
+                                &mut *transaction,
+                                &system_prompt,
+                                new_conversation.id,
+                            )
+                            .await?;
+                        }
+                        transaction.commit().await?;
                         self.conversations.push(new_conversation);
                         self.new_conversation_popup.deactivate();
                     }
@@ -195,14 +462,7 @@ impl App {
 
                 // enter triggers deletion - this is default behaviour
                 if self.delete_conversation_popup.is_activated() {
-                    if let Some(conversation) = self.conversations.currently_selected() {
-                        db::delete_conversation(&self.sqlite, conversation.id).await?;
-                        self.conversations.delete_conversation(conversation);
-                        self.chat.reset();
-                    }
-                    self.delete_conversation_popup.deactivate();
-
-                    return Ok(());
+                    return self.delete_selected_conversation().await;
                 }
 
                 // NOTE: crossterm currently cannot recognise combination of Enter+Shift.
@@ -212,8 +472,12 @@ impl App {
                     if key_event.modifiers == KeyModifiers::SHIFT {
                         self.prompt.new_line();
                     } else {
-                        // we're able to send only when we have selected conversation
-                        if let Some(conversation) = self.conversations.currently_selected() {
+                        // we're able to send only when we have selected conversation, that isn't in trash
+                        if let Some(conversation) = self
+                            .conversations
+                            .currently_selected()
+                            .filter(|conversation| conversation.deleted_at.is_none())
+                        {
                             let user_prompt = self.prompt.get_content();
                             let user_message = db::create_message(
                                 &self.sqlite,
@@ -244,7 +508,13 @@ impl App {
                         self.event_tx.send(Event::ChatBottomScroll)?;
                     }
                 }
-                AppFocus::Messages => self.chat.scroll_down(),
+                AppFocus::Messages => {
+                    if key_event.modifiers == KeyModifiers::SHIFT {
+                        self.chat.select_next_message();
+                    } else {
+                        self.chat.scroll_down();
+                    }
+                }
                 AppFocus::Prompt => {
                     self.prompt.handle_input(key_event);
                 }
@@ -263,7 +533,13 @@ impl App {
                         self.event_tx.send(Event::ChatBottomScroll)?;
                     }
                 }
-                AppFocus::Messages => self.chat.scroll_up(),
+                AppFocus::Messages => {
+                    if key_event.modifiers == KeyModifiers::SHIFT {
+                        self.chat.select_previous_message();
+                    } else {
+                        self.chat.scroll_up();
+                    }
+                }
                 AppFocus::Prompt => {
                     self.prompt.handle_input(key_event);
                 }
@@ -281,18 +557,48 @@ impl App {
                     return Ok(());
                 }
 
-                if let AppFocus::Conversation = self.current_focus() {
-                    self.conversations.unselect();
-                    self.chat.reset();
+                match self.current_focus() {
+                    AppFocus::Conversation => {
+                        self.conversations.unselect();
+                        self.chat.reset();
+                    }
+                    AppFocus::Messages if self.chat.is_finding() => {
+                        self.find_bar.clear();
+                        self.chat.find(None);
+                    }
+                    AppFocus::Messages if self.chat.selected_message().is_some() => {
+                        self.chat.unselect_message();
+                    }
+                    // stops generating response, noop when assistant is idle
+                    AppFocus::Messages | AppFocus::Prompt => self.inference_cancellation.cancel(),
+                }
+            }
+            KeyCode::Left | KeyCode::Right => {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.handle_input(key_event);
+
+                    return Ok(());
+                }
+
+                match self.current_focus() {
+                    AppFocus::Messages => {
+                        self.switch_branch(key_event.code == KeyCode::Right).await?
+                    }
+                    AppFocus::Prompt => self.prompt.handle_input(key_event),
+                    AppFocus::Conversation => {}
                 }
             }
             KeyCode::Tab => {
-                if !self.new_conversation_popup.is_activated() {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.toggle_field();
+                } else {
                     self.next_focus()
                 }
             }
             KeyCode::BackTab => {
-                if !self.new_conversation_popup.is_activated() {
+                if self.new_conversation_popup.is_activated() {
+                    self.new_conversation_popup.toggle_field();
+                } else {
                     self.previous_focus()
                 }
             }
@@ -326,6 +632,407 @@ impl App {
         Ok(())
     }
 
+    /// Conversation in trash is moved there, the one in trash view is deleted permanently.
+    async fn delete_selected_conversation(&mut self) -> AppResult<()> {
+        if let Some(conversation) = self.conversations.currently_selected() {
+            if self.conversations.is_showing_trash() {
+                db::delete_conversation(&self.sqlite, conversation.id).await?;
+                self.backend.forget(conversation.clone()).await;
+                self.conversations.delete_conversation(conversation);
+            } else {
+                let conversation = db::trash_conversation(&self.sqlite, conversation.id).await?;
+                self.conversations.trash_conversation(conversation);
+            }
+            self.chat.reset();
+        }
+        self.delete_conversation_popup.deactivate();
+
+        Ok(())
+    }
+
+    /// Restores conversation selected in trash view, otherwise undoes the last deletion.
+    async fn restore_conversation(&mut self) -> AppResult<()> {
+        let conversation = match self.conversations.is_showing_trash() {
+            true => self.conversations.currently_selected(),
+            false => self.conversations.last_trashed(),
+        };
+        if let Some(conversation) = conversation {
+            let conversation = db::restore_conversation(&self.sqlite, conversation.id).await?;
+            let conversation_id = conversation.id;
+            self.conversations.restore_conversation(conversation);
+            self.conversations.select_conversation(conversation_id);
+            self.chat.load_messages(conversation_id).await?;
+            self.event_tx.send(Event::ChatBottomScroll)?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks assistant to answer the last user message again, new response starts another branch.
+    async fn regenerate_last_response(&mut self) -> AppResult<()> {
+        // conversations in trash are read-only
+        if self.conversations.is_showing_trash() {
+            return Ok(());
+        }
+        let Some(last_message) = self.chat.get_last_message() else {
+            return Ok(());
+        };
+        if last_message.role != Role::Assistant {
+            return Ok(());
+        }
+        let Some(user_message) = last_message
+            .parent_id
+            .and_then(|parent_id| self.chat.get_message(parent_id))
+            .cloned()
+        else {
+            return Ok(());
+        };
+
+        self.inference_tx.send(user_message).await?;
+
+        Ok(())
+    }
+
+    /// Switches to previous or next version of selected message, the last message is used
+    /// when nothing is selected.
+    async fn switch_branch(&mut self, forward: bool) -> AppResult<()> {
+        // conversations in trash are read-only
+        if self.conversations.is_showing_trash() {
+            return Ok(());
+        }
+        let Some(message) = self
+            .chat
+            .selected_message()
+            .or(self.chat.get_last_message())
+            .cloned()
+        else {
+            return Ok(());
+        };
+
+        let branches = db::list_branches(&self.sqlite, message.id).await?;
+        let Some(position) = branches.iter().position(|branch| branch.id == message.id) else {
+            return Ok(());
+        };
+        let position = match forward {
+            true => position + 1,
+            false => match position.checked_sub(1) {
+                Some(position) => position,
+                None => return Ok(()),
+            },
+        };
+        let Some(branch) = branches.get(position) else {
+            return Ok(());
+        };
+
+        let was_selected = self.chat.selected_message().is_some();
+        db::switch_branch(&self.sqlite, branch.id).await?;
+        self.chat.load_messages(message.conversation_id).await?;
+        if was_selected {
+            self.chat.select_message(branch.id);
+        }
+
+        Ok(())
+    }
+
+    /// Opens conversation of the search hit and scrolls chat to the matching message,
+    /// message that belongs to another branch becomes a part of the current one.
+    async fn jump_to_search_hit(&mut self) -> AppResult<()> {
+        let Some(hit) = self.search_popup.selected_hit().cloned() else {
+            return Ok(());
+        };
+
+        self.conversations.select_conversation(hit.conversation_id);
+        self.chat.load_messages(hit.conversation_id).await?;
+        if self.chat.get_message(hit.message_id).is_none() {
+            db::switch_branch(&self.sqlite, hit.message_id).await?;
+            self.chat.load_messages(hit.conversation_id).await?;
+        }
+        self.chat.select_message(hit.message_id);
+        self.chat.scroll_to_message(hit.message_id);
+        self.focus = AppFocus::Messages;
+        self.search_popup.deactivate();
+
+        Ok(())
+    }
+
+    async fn handle_search_popup_key_events(&mut self, key_event: KeyEvent) -> AppResult<()> {
+        match key_event.code {
+            KeyCode::Char('c') | KeyCode::Char('C')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.running = false;
+            }
+            KeyCode::Enter => self.jump_to_search_hit().await?,
+            KeyCode::Esc => self.search_popup.deactivate(),
+            KeyCode::Up => self.search_popup.up(),
+            KeyCode::Down => self.search_popup.down(),
+            _ => {
+                self.search_popup.handle_input(key_event);
+                let hits =
+                    db::search_messages(&self.sqlite, &self.search_popup.get_query(), SEARCH_LIMIT)
+                        .await?;
+                self.search_popup.set_hits(hits);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Embeds new messages and shows conversations related to the selected one.
+    async fn show_related_conversations(&mut self) -> AppResult<()> {
+        let (Some(embedder), Some(conversation)) = (
+            self.embedder.clone(),
+            self.conversations.currently_selected(),
+        ) else {
+            return Ok(());
+        };
+
+        embeddings::embed_messages(&self.sqlite, embedder.as_ref()).await?;
+        let related = embeddings::related_conversations(
+            &self.sqlite,
+            &embedder.model(),
+            conversation.id,
+            RELATED_CONVERSATIONS_LIMIT,
+        )
+        .await?;
+        self.new_conversation_popup.deactivate();
+        self.delete_conversation_popup.deactivate();
+        self.related_conversations_popup.activate(related);
+
+        Ok(())
+    }
+
+    async fn handle_related_conversations_popup_key_events(
+        &mut self,
+        key_event: KeyEvent,
+    ) -> AppResult<()> {
+        match key_event.code {
+            KeyCode::Char('c') | KeyCode::Char('C')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.running = false;
+            }
+            KeyCode::Enter => {
+                if let Some(related) = self.related_conversations_popup.selected() {
+                    let conversation_id = related.conversation.id;
+                    self.conversations.select_conversation(conversation_id);
+                    self.chat.load_messages(conversation_id).await?;
+                    self.event_tx.send(Event::ChatBottomScroll)?;
+                }
+                self.related_conversations_popup.deactivate();
+            }
+            KeyCode::Esc => self.related_conversations_popup.deactivate(),
+            KeyCode::Up => self.related_conversations_popup.up(),
+            KeyCode::Down => self.related_conversations_popup.down(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_find_bar_key_events(&mut self, key_event: KeyEvent) -> AppResult<()> {
+        match key_event.code {
+            KeyCode::Char('c') | KeyCode::Char('C')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.running = false;
+            }
+            // keeps matches highlighted, so user can jump between them
+            KeyCode::Enter => self.find_bar.deactivate(),
+            KeyCode::Esc => {
+                self.find_bar.deactivate();
+                self.find_bar.clear();
+                self.chat.find(None);
+            }
+            _ => {
+                self.find_bar.handle_input(key_event);
+                self.chat.find(self.find_bar.get_query());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_export_conversation_popup_key_events(
+        &mut self,
+        key_event: KeyEvent,
+    ) -> AppResult<()> {
+        match key_event.code {
+            KeyCode::Char('c') | KeyCode::Char('C')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.running = false;
+            }
+            KeyCode::Enter => {
+                if let (Some(dir), Some(conversation)) = (
+                    self.export_conversation_popup.get_dir(),
+                    self.export_conversation_popup.conversation(),
+                ) {
+                    // popup stays open, so user can see where conversation was exported
+                    let status = match export::export_conversation(
+                        &self.sqlite,
+                        conversation.id,
+                        self.export_conversation_popup.format(),
+                        &dir,
+                    )
+                    .await
+                    {
+                        Ok(path) => format!("Exported to {}", path.display()),
+                        Err(err) => format!("Cannot export: {err}"),
+                    };
+                    self.export_conversation_popup.set_status(status);
+                }
+            }
+            KeyCode::Tab => self.export_conversation_popup.next_format(),
+            KeyCode::Esc => self.export_conversation_popup.deactivate(),
+            _ => self.export_conversation_popup.handle_input(key_event),
+        }
+
+        Ok(())
+    }
+
+    async fn handle_rename_conversation_popup_key_events(
+        &mut self,
+        key_event: KeyEvent,
+    ) -> AppResult<()> {
+        match key_event.code {
+            KeyCode::Char('c') | KeyCode::Char('C')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.running = false;
+            }
+            KeyCode::Enter => {
+                if let (Some(name), Some(conversation)) = (
+                    self.rename_conversation_popup.get_content(),
+                    self.rename_conversation_popup.conversation(),
+                ) {
+                    let conversation =
+                        db::update_conversation_name(&self.sqlite, conversation.id, &name).await?;
+                    self.conversations.update_conversation(conversation);
+                }
+                self.rename_conversation_popup.deactivate();
+            }
+            KeyCode::Esc => self.rename_conversation_popup.deactivate(),
+            _ => self.rename_conversation_popup.handle_input(key_event),
+        }
+
+        Ok(())
+    }
+
+    async fn handle_edit_message_popup_key_events(&mut self, key_event: KeyEvent) -> AppResult<()> {
+        match key_event.code {
+            KeyCode::Char('c') | KeyCode::Char('C')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.running = false;
+            }
+            KeyCode::Enter => {
+                if let (Some(content), Some(message)) = (
+                    self.edit_message_popup.get_content(),
+                    self.edit_message_popup.message().cloned(),
+                ) {
+                    // edited message starts new branch, original one is kept with its follow-ups
+                    let edited_message = db::create_child_message(
+                        &self.sqlite,
+                        Role::User,
+                        &content,
+                        message.conversation_id,
+                        message.parent_id,
+                    )
+                    .await?;
+
+                    self.chat.load_messages(message.conversation_id).await?;
+                    self.event_tx.send(Event::ChatBottomScroll)?;
+                    self.inference_tx.send(edited_message).await?;
+                }
+                self.edit_message_popup.deactivate();
+            }
+            KeyCode::Esc => self.edit_message_popup.deactivate(),
+            _ => self.edit_message_popup.handle_input(key_event),
+        }
+
+        Ok(())
+    }
+
+    async fn handle_conversation_settings_popup_key_events(
+        &mut self,
+        key_event: KeyEvent,
+    ) -> AppResult<()> {
+        match key_event.code {
+            KeyCode::Char('c') | KeyCode::Char('C')
+                if key_event.modifiers == KeyModifiers::CONTROL =>
+            {
+                self.running = false;
+            }
+            KeyCode::Enter => {
+                if let Some(settings) = self.conversation_settings_popup.get_settings() {
+                    if let Some(Err(err)) = settings
+                        .model
+                        .as_deref()
+                        .map(|model| self.backend.validate_model(model))
+                    {
+                        self.conversation_settings_popup.set_error(err.to_string());
+
+                        return Ok(());
+                    }
+                    if let Some(conversation) = self.conversations.currently_selected() {
+                        let system_prompt = self.conversation_settings_popup.get_system_prompt();
+
+                        let mut transaction = self.sqlite.begin().await?;
+                        let updated_conversation = db::update_conversation_settings(
+                            &mut *transaction,
+                            conversation.id,
+                            &settings,
+                        )
+                        .await?;
+                        let system_prompt_changed = match (
+                            db::get_system_prompt(&mut *transaction, conversation.id).await?,
+                            system_prompt,
+                        ) {
+                            (Some(message), Some(content)) => {
+                                db::update_message(&mut *transaction, &content, message.id).await?;
+                                message.content != content
+                            }
+                            (Some(message), None) => {
+                                db::delete_message(&mut *transaction, message.id).await?;
+                                true
+                            }
+                            (None, Some(content)) => {
+                                db::create_system_prompt(
+                                    &mut *transaction,
+                                    &content,
+                                    conversation.id,
+                                )
+                                .await?;
+                                true
+                            }
+                            (None, None) => false,
+                        };
+                        transaction.commit().await?;
+                        // backend's session keeps the system prompt it was created with
+                        if system_prompt_changed {
+                            let history = db::get_messages(&self.sqlite, conversation.id).await?;
+                            self.backend
+                                .rebuild_session(updated_conversation.clone(), history)
+                                .await?;
+                        }
+
+                        self.conversations.update_conversation(updated_conversation);
+                        self.chat.load_messages(conversation.id).await?;
+                        self.event_tx.send(Event::ChatBottomScroll)?;
+                    }
+                    self.conversation_settings_popup.deactivate();
+                }
+            }
+            KeyCode::Esc => self.conversation_settings_popup.deactivate(),
+            KeyCode::Up | KeyCode::BackTab => self.conversation_settings_popup.previous_field(),
+            KeyCode::Down | KeyCode::Tab => self.conversation_settings_popup.next_field(),
+            _ => self.conversation_settings_popup.handle_input(key_event),
+        }
+
+        Ok(())
+    }
+
     async fn handle_inference_event(&mut self, message: Message) -> AppResult<()> {
         self.chat.push_message(message);
 
@@ -336,6 +1043,8 @@ impl App {
         if let Some(last_message) = self.chat.get_last_message() {
             if let Some(conversation) = self.conversations.currently_selected() {
                 if conversation.id.eq(&message.conversation_id) {
+                    // response that shows up for the first time may start new branch
+                    let new_response = last_message.id != message.id;
                     match last_message.role {
                         Role::Assistant => {
                             self.chat.pop_message();
@@ -346,6 +1055,11 @@ impl App {
                             self.chat.push_message(message);
                         }
                     }
+                    if new_response {
+                        self.chat.load_branches(conversation.id).await?;
+                        // older messages could get summarized before the response was generated
+                        self.chat.load_summary(conversation.id).await?;
+                    }
                 }
             }
         };
@@ -353,6 +1067,19 @@ impl App {
         Ok(())
     }
 
+    async fn handle_inference_failed_event(
+        &mut self,
+        message: Message,
+        error: String,
+    ) -> AppResult<()> {
+        self.chat.set_error(message.id, error);
+        if message.role == Role::Assistant {
+            self.handle_inference_stream_event(message).await?;
+        }
+
+        Ok(())
+    }
+
     async fn handle_chat_bottom_scroll_event(&mut self) -> AppResult<()> {
         self.chat.scroll_to_bottom();
 
@@ -375,8 +1102,104 @@ impl App {
             Event::Inference(message, InferenceType::NonStreaming) => {
                 self.handle_inference_event(message).await
             }
+            Event::InferenceFailed(message, error) => {
+                self.handle_inference_failed_event(message, error).await
+            }
+            Event::ConversationRenamed(conversation) => {
+                self.conversations.update_conversation(conversation);
+                Ok(())
+            }
             Event::ChatBottomScroll => self.handle_chat_bottom_scroll_event().await,
             Event::PromptTranscription(word) => self.handle_prompt_transcription(word).await,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    /// Returns initialized app together with receiver of its events, app fails to send them
+    /// once receiver is dropped.
+    async fn app(
+        pool: &SqlitePool,
+        backend: Arc<MockBackend>,
+    ) -> AppResult<(App, UnboundedReceiver<Event>)> {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let mut app = App::new(pool.clone(), event_tx, backend, Default::default());
+        app.init().await?;
+
+        Ok((app, event_rx))
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_changed_system_prompt_rebuilds_session(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = Arc::new(MockBackend::echo());
+        let (mut app, _event_rx) = app(&pool, backend.clone()).await?;
+        app.conversations.select_conversation(1);
+
+        // when
+        app.handle_key_events(ctrl('s')).await?;
+        for _ in 0..5 {
+            app.handle_key_events(key(KeyCode::Tab)).await?;
+        }
+        for c in "Be brief".chars() {
+            app.handle_key_events(key(KeyCode::Char(c))).await?;
+        }
+        app.handle_key_events(key(KeyCode::Enter)).await?;
+
+        // then
+        let system_prompt = db::get_system_prompt(&pool, 1).await?;
+        assert_eq!(
+            system_prompt.map(|message| message.content),
+            Some("Be brief".to_string())
+        );
+        assert_eq!(backend.rebuilt_sessions(), vec![1]);
+
+        // when
+        app.handle_key_events(ctrl('s')).await?;
+        app.handle_key_events(key(KeyCode::Enter)).await?;
+
+        // then
+        // session is still valid when system prompt stays the same
+        assert_eq!(backend.rebuilt_sessions(), vec![1]);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_trash_view_is_read_only(pool: SqlitePool) -> AppResult<()> {
+        // given
+        db::trash_conversation(&pool, 1).await?;
+        let (mut app, _event_rx) = app(&pool, Arc::new(MockBackend::echo())).await?;
+        app.handle_key_events(key(KeyCode::Char('t'))).await?;
+        app.handle_key_events(key(KeyCode::Up)).await?;
+        assert!(app.conversations.is_showing_trash());
+        assert_eq!(
+            app.conversations.currently_selected().map(|c| c.id),
+            Some(1)
+        );
+
+        // when
+        app.handle_key_events(ctrl('s')).await?;
+        app.handle_key_events(key(KeyCode::Char('r'))).await?;
+
+        // then
+        assert!(!app.conversation_settings_popup.is_activated());
+        assert!(!app.rename_conversation_popup.is_activated());
+
+        Ok(())
+    }
+}
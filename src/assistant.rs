@@ -1,12 +1,17 @@
+use std::sync::Arc;
+
+use chrono::Utc;
 use futures::StreamExt;
-use kalosm::language::{Chat, Llama};
 use sqlx::SqlitePool;
 use tokio::sync::mpsc::{Receiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
+    backend::{Cancellation, InferenceBackend, InferenceRequest},
+    context::{self, ContextPolicy, ContextWindow},
     db,
     event::{Event, InferenceType},
-    models::{Message, Role},
+    models::{Conversation, Message, Role},
     AppResult,
 };
 
@@ -14,14 +19,21 @@ pub struct Assistant;
 
 impl Assistant {
     pub fn run(
-        llama: Llama,
+        backend: Arc<dyn InferenceBackend>,
         sqlite: SqlitePool,
         inference_rx: Receiver<Message>,
         event_tx: UnboundedSender<Event>,
+        cancellation: Arc<Cancellation>,
+        context_window: ContextWindow,
     ) {
-        tokio::spawn(async move {
-            inference_stream(sqlite, inference_rx, event_tx, llama).await;
-        });
+        tokio::spawn(inference_stream(
+            sqlite,
+            inference_rx,
+            event_tx,
+            backend,
+            cancellation,
+            context_window,
+        ));
     }
 }
 
@@ -29,43 +41,790 @@ async fn inference_stream(
     sqlite: SqlitePool,
     mut inference_rx: Receiver<Message>,
     event_tx: UnboundedSender<Event>,
-    llama: Llama,
-) -> AppResult<()> {
+    backend: Arc<dyn InferenceBackend>,
+    cancellation: Arc<Cancellation>,
+    context_window: ContextWindow,
+) {
     while let Some(inference_message) = inference_rx.recv().await {
-        let conversation = db::get_conversation(&sqlite, inference_message.conversation_id).await?;
-
-        // TODO: create cache object that could keep different chats in memory for some time and load it when necessary
-        let mut chat = Chat::builder(llama.clone())
-            .with_try_session_path(&conversation.session_path)
-            .build();
-        let mut text_stream = chat.add_message(inference_message.content);
-
-        let mut assistant_response =
-            db::create_message(&sqlite, Role::Assistant, "", conversation.id).await?;
-
-        while let Some(chunk) = text_stream.next().await {
-            assistant_response.content.push_str(&chunk);
-
-            // ignore send errors, I can at least wait until the end of assistant's response and save it to db
-            // if the channel is closed we probably paniced anyway
-            let _ = event_tx.send(Event::Inference(
-                assistant_response.clone(),
-                InferenceType::Streaming,
-            ));
+        // failed response doesn't stop the assistant, the next message gets another try
+        if let Err(err) = respond(
+            &sqlite,
+            &event_tx,
+            backend.as_ref(),
+            &cancellation,
+            &context_window,
+            &inference_message,
+        )
+        .await
+        {
+            let err = err.to_string();
+            tracing::error!(
+                "cannot respond to message: {:?}: {}",
+                inference_message.id,
+                err
+            );
+            let _ = event_tx.send(Event::InferenceFailed(inference_message, err));
         }
+    }
+}
 
+/// Generates and stores response to given message.
+async fn respond(
+    sqlite: &SqlitePool,
+    event_tx: &UnboundedSender<Event>,
+    backend: &dyn InferenceBackend,
+    cancellation: &Cancellation,
+    context_window: &ContextWindow,
+    inference_message: &Message,
+) -> AppResult<()> {
+    let cancellation_token = cancellation.renew();
+    let conversation = db::get_conversation(sqlite, inference_message.conversation_id).await?;
+    let mut history = db::get_messages_until(sqlite, inference_message.id).await?;
+    count_tokens(sqlite, backend, &mut history).await?;
+    // backend's session ends with the most recent response, it's out of date
+    // when message was sent on another branch (e.g. edited or regenerated)
+    let last_response = db::get_last_response(sqlite, conversation.id).await?;
+    let rebuild_session = last_response.map(|message| message.id)
+        != history
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .map(|message| message.id);
+    // once the window moves, history doesn't start where backend's session does,
+    // session is rebuilt from what's left
+    let (history, rebuild_session) =
+        match fit_into_context(sqlite, backend, &conversation, &history, context_window).await? {
+            Some((history, moved)) => (history, rebuild_session || moved),
+            None => (history, rebuild_session),
+        };
+
+    // response is created upfront, so messages sent in the meantime follow it up
+    let mut assistant_response = db::create_child_message(
+        sqlite,
+        Role::Assistant,
+        "",
+        conversation.id,
+        Some(inference_message.id),
+    )
+    .await?;
+
+    let request = InferenceRequest {
+        conversation: conversation.clone(),
+        history,
+        rebuild_session,
+    };
+    let result = stream_response(
+        event_tx,
+        backend,
+        cancellation_token,
+        request,
+        &mut assistant_response,
+    )
+    .await
+    .map_err(|err| err.to_string());
+    if let Err(err) = result {
+        // response is kept with whatever was generated before the failure
         let assistant_response =
-            db::update_message(&sqlite, &assistant_response.content, assistant_response.id).await?;
-        chat.add_message(assistant_response.content);
+            db::interrupt_message(sqlite, &assistant_response.content, assistant_response.id)
+                .await?;
+        tracing::error!(
+            "cannot generate response: {:?}: {}",
+            assistant_response.id,
+            err
+        );
+        let _ = event_tx.send(Event::InferenceFailed(assistant_response, err));
 
-        tokio::spawn(async move {
-            match chat.save_session(&conversation.session_path).await {
-                Ok(_) => tracing::info!("session saved to disk"),
-                Err(err) => tracing::error!("Error while saving session: {}", err),
+        return Ok(());
+    }
+
+    if assistant_response.interrupted {
+        let assistant_response =
+            db::interrupt_message(sqlite, &assistant_response.content, assistant_response.id)
+                .await?;
+        let _ = event_tx.send(Event::Inference(
+            assistant_response,
+            InferenceType::Streaming,
+        ));
+    } else {
+        let assistant_response =
+            db::update_message(sqlite, &assistant_response.content, assistant_response.id).await?;
+
+        if conversation.name.is_empty() {
+            // conversation stays unnamed when title cannot be generated, next response gives it another try
+            match name_conversation(sqlite, backend, &conversation, &assistant_response).await {
+                Ok(Some(conversation)) => {
+                    let _ = event_tx.send(Event::ConversationRenamed(conversation));
+                }
+                Ok(None) => {}
+                Err(err) => tracing::error!("cannot generate conversation title: {}", err),
             }
-        })
-        .await?;
+        }
     }
 
     Ok(())
 }
+
+/// Streams backend's response into given message and sends every chunk to the UI.
+async fn stream_response(
+    event_tx: &UnboundedSender<Event>,
+    backend: &dyn InferenceBackend,
+    cancellation_token: CancellationToken,
+    request: InferenceRequest,
+    assistant_response: &mut Message,
+) -> AppResult<()> {
+    let mut text_stream = backend.stream(request).await?;
+
+    loop {
+        tokio::select! {
+            chunk = text_stream.next() => {
+                let Some(chunk) = chunk else {
+                    break;
+                };
+                // error reported by backend ends the response
+                let chunk = chunk?;
+                // backend may still emit chunks that were produced before it noticed cancellation
+                if assistant_response.interrupted {
+                    continue;
+                }
+                assistant_response.content.push_str(&chunk);
+
+                // ignore send errors, I can at least wait until the end of assistant's response and save it to db
+                // if the channel is closed we probably paniced anyway
+                let _ = event_tx.send(Event::Inference(
+                    assistant_response.clone(),
+                    InferenceType::Streaming,
+                ));
+            }
+            _ = cancellation_token.cancelled(), if !assistant_response.interrupted => {
+                // I keep reading the stream until backend closes it, so next message
+                // doesn't start before backend cleans up after cancelled generation
+                backend.cancel();
+                assistant_response.interrupted = true;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts tokens of messages that weren't counted yet and stores them.
+async fn count_tokens(
+    sqlite: &SqlitePool,
+    backend: &dyn InferenceBackend,
+    history: &mut [Message],
+) -> AppResult<()> {
+    for message in history
+        .iter_mut()
+        .filter(|message| message.token_count.is_none())
+    {
+        let token_count = backend.count_tokens(&message.content) as u32;
+        *message = db::update_token_count(sqlite, token_count, message.id).await?;
+    }
+
+    Ok(())
+}
+
+/// Truncates history that doesn't fit into the context window according to window's policy,
+/// returns `None` when the whole history fits. Truncated history comes with a flag telling whether
+/// the window moved since the previous turn.
+async fn fit_into_context(
+    sqlite: &SqlitePool,
+    backend: &dyn InferenceBackend,
+    conversation: &Conversation,
+    history: &[Message],
+    context_window: &ContextWindow,
+) -> AppResult<Option<(Vec<Message>, bool)>> {
+    let budget = context_window.budget(&conversation.settings);
+    let out_of_context = context_window.out_of_context(history, budget);
+    if out_of_context.is_empty() {
+        return Ok(None);
+    }
+    // previous turn ended with the prompt that precedes the last response
+    let previous_out_of_context = history
+        .len()
+        .checked_sub(2)
+        .map(|end| context_window.out_of_context(&history[..end], budget))
+        .unwrap_or_default();
+    let mut moved = out_of_context != previous_out_of_context;
+
+    let (mut dropped, mut history): (Vec<_>, Vec<_>) = history
+        .iter()
+        .cloned()
+        .partition(|message| out_of_context.contains(&message.id));
+    if context_window.policy == ContextPolicy::Summarize {
+        // window moves together with the summary, the same summary is used as long as
+        // no other messages are dropped
+        moved = false;
+        // summary may take more room than it was left for it, the oldest turns are summarized
+        // as well until it fits
+        loop {
+            let (summary, generated) = summarize(sqlite, backend, conversation, &dropped).await?;
+            moved |= generated;
+            let summary = Message {
+                content: format!(
+                    "Summary of the earlier part of the conversation:\n{}",
+                    summary.content
+                ),
+                ..summary
+            };
+            // summary goes right after system prompt, it's followed by the most recent turns
+            let position = history
+                .iter()
+                .take_while(|message| message.role == Role::System)
+                .count();
+            history.insert(position, summary);
+
+            let out_of_context =
+                context_window.out_of_context(&history, budget + context::SUMMARY_TOKENS);
+            if out_of_context.is_empty() {
+                break;
+            }
+            history.remove(position);
+            let (more_dropped, rest): (Vec<_>, Vec<_>) = history
+                .into_iter()
+                .partition(|message| out_of_context.contains(&message.id));
+            dropped.extend(more_dropped);
+            history = rest;
+        }
+    }
+
+    Ok(Some((history, moved)))
+}
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation below, extend the summary of earlier \
+    messages when it's given. Be concise, but keep facts, names and decisions that may be needed \
+    to continue the conversation.";
+
+/// Returns summary of given messages, together with a flag telling whether it was just generated.
+/// Summaries are stored, so the latest one is only extended with messages it doesn't cover yet.
+async fn summarize(
+    sqlite: &SqlitePool,
+    backend: &dyn InferenceBackend,
+    conversation: &Conversation,
+    messages: &[Message],
+) -> AppResult<(Message, bool)> {
+    let summaries = db::get_summaries(sqlite, conversation.id).await?;
+    let (previous_summary, new_messages) = match context::latest_summary(&summaries, messages) {
+        Some((index, summary)) => (Some(summary), &messages[index + 1..]),
+        None => (None, messages),
+    };
+    let Some(last_message) = new_messages.last() else {
+        return previous_summary
+            .cloned()
+            .map(|summary| (summary, false))
+            .ok_or_else(|| "cannot summarize empty history".into());
+    };
+
+    let transcript = previous_summary
+        .map(|summary| format!("summary of earlier messages: {}", summary.content))
+        .into_iter()
+        .chain(
+            new_messages
+                .iter()
+                .map(|message| format!("{}: {}", message.role, message.content)),
+        )
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let mut summary_conversation = conversation.clone();
+    summary_conversation.settings.max_tokens = Some(context::SUMMARY_TOKENS as u32);
+    let summary = backend
+        .complete(
+            summary_conversation,
+            vec![
+                message(Role::System, SUMMARY_PROMPT, conversation.id),
+                message(Role::User, &transcript, conversation.id),
+            ],
+        )
+        .await?;
+
+    let summary =
+        db::create_summary(sqlite, summary.trim(), conversation.id, last_message.id).await?;
+
+    Ok((summary, true))
+}
+
+const TITLE_PROMPT: &str = "Write a short title of the conversation below, at most 6 words. \
+    Reply with the title only, without quotes.";
+const MAX_TITLE_LENGTH: usize = 60;
+
+/// Generates title from the exchange that ends with given response and names the conversation with it,
+/// returns `None` when conversation was named in the meantime.
+async fn name_conversation(
+    sqlite: &SqlitePool,
+    backend: &dyn InferenceBackend,
+    conversation: &Conversation,
+    response: &Message,
+) -> AppResult<Option<Conversation>> {
+    let transcript = db::get_messages_until(sqlite, response.id)
+        .await?
+        .iter()
+        .filter(|message| message.role != Role::System)
+        .map(|message| format!("{}: {}", message.role, message.content))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let mut title_conversation = conversation.clone();
+    title_conversation.settings.max_tokens = Some(32);
+    let title = backend
+        .complete(
+            title_conversation,
+            vec![
+                message(Role::System, TITLE_PROMPT, conversation.id),
+                message(Role::User, &transcript, conversation.id),
+            ],
+        )
+        .await?;
+
+    let title = title
+        .lines()
+        .map(|line| line.trim().trim_matches(['"', '\'', '*', '#']).trim())
+        .find(|line| !line.is_empty())
+        .ok_or("model generated empty title")?
+        .chars()
+        .take(MAX_TITLE_LENGTH)
+        .collect::<String>();
+
+    db::set_name_if_unnamed(sqlite, conversation.id, &title).await
+}
+
+/// Creates message that is only sent to the model, it isn't stored.
+fn message(role: Role, content: &str, conversation_id: u32) -> Message {
+    Message {
+        id: 0,
+        role,
+        content: content.to_string(),
+        conversation_id,
+        parent_id: None,
+        created_at: Utc::now(),
+        interrupted: false,
+        token_count: None,
+        summary: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use futures::{future::BoxFuture, FutureExt};
+    use sqlx::SqlitePool;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::backend::{mock::MockBackend, ModelInfo, TextStream};
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_inference_stream(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = Arc::new(MockBackend::canned(vec![
+            "Because of Rayleigh scattering".to_string()
+        ])?);
+        let (inference_tx, inference_rx) = mpsc::channel(10);
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let user_message = db::create_message(&pool, Role::User, "why is the sky blue?", 3).await?;
+
+        // when
+        inference_tx.send(user_message.clone()).await?;
+        drop(inference_tx);
+        inference_stream(
+            pool.clone(),
+            inference_rx,
+            event_tx,
+            backend,
+            Default::default(),
+            Default::default(),
+        )
+        .await;
+
+        // then
+        let mut streamed = vec![];
+        while let Ok(Event::Inference(message, InferenceType::Streaming)) = event_rx.try_recv() {
+            streamed.push(message.content);
+        }
+        assert_eq!(
+            streamed,
+            vec![
+                "Because ",
+                "Because of ",
+                "Because of Rayleigh ",
+                "Because of Rayleigh scattering"
+            ]
+        );
+
+        let messages = db::get_messages(&pool, 3).await?;
+        assert_eq!(messages.len(), 2);
+        // prompt is counted before it is sent to the model
+        assert_eq!(
+            messages[0],
+            Message {
+                token_count: Some(5),
+                ..user_message
+            }
+        );
+        assert_eq!(messages[1].role, Role::Assistant);
+        assert_eq!(messages[1].content, "Because of Rayleigh scattering");
+        assert!(!messages[1].interrupted);
+
+        Ok(())
+    }
+
+    /// Fails the first request, like a server that wasn't started yet.
+    struct FailingOnceBackend {
+        inner: MockBackend,
+        failed: AtomicBool,
+    }
+
+    impl InferenceBackend for FailingOnceBackend {
+        fn model_info(&self) -> ModelInfo {
+            self.inner.model_info()
+        }
+
+        fn stream(&self, request: InferenceRequest) -> BoxFuture<'_, AppResult<TextStream>> {
+            if !self.failed.swap(true, Ordering::SeqCst) {
+                return async { Err("connection refused".into()) }.boxed();
+            }
+
+            self.inner.stream(request)
+        }
+
+        fn cancel(&self) {
+            self.inner.cancel();
+        }
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_inference_stream_recovers_from_failure(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = Arc::new(FailingOnceBackend {
+            inner: MockBackend::echo(),
+            failed: AtomicBool::new(false),
+        });
+        let (inference_tx, inference_rx) = mpsc::channel(10);
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let first_message =
+            db::create_message(&pool, Role::User, "why is the sky blue?", 3).await?;
+
+        // when
+        inference_tx.send(first_message).await?;
+        let handle = tokio::spawn(inference_stream(
+            pool.clone(),
+            inference_rx,
+            event_tx,
+            backend,
+            Default::default(),
+            Default::default(),
+        ));
+        let failed = loop {
+            if let Some(Event::InferenceFailed(message, error)) = event_rx.recv().await {
+                break (message, error);
+            }
+        };
+        let second_message = db::create_message(&pool, Role::User, "are you sure?", 3).await?;
+        inference_tx.send(second_message).await?;
+        drop(inference_tx);
+        handle.await?;
+
+        // then
+        assert_eq!(failed.0.role, Role::Assistant);
+        assert!(failed.0.interrupted);
+        assert_eq!(failed.1, "connection refused");
+        let messages = db::get_messages(&pool, 3).await?;
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1].id, failed.0.id);
+        assert_eq!(messages[3].role, Role::Assistant);
+        assert_eq!(messages[3].content, "are you sure?");
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_inference_stream_cancelled(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = Arc::new(
+            MockBackend::canned(vec![
+                "Because of Rayleigh scattering".to_string(),
+                "Sunlight is scattered by molecules of the air".to_string(),
+            ])?
+            .with_token_delay(Duration::from_millis(50)),
+        );
+        let cancellation = Arc::new(Cancellation::default());
+        let (inference_tx, inference_rx) = mpsc::channel(10);
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let first_message =
+            db::create_message(&pool, Role::User, "why is the sky blue?", 3).await?;
+        let handle = tokio::spawn({
+            let pool = pool.clone();
+            let cancellation = cancellation.clone();
+            async move {
+                inference_stream(
+                    pool,
+                    inference_rx,
+                    event_tx,
+                    backend,
+                    cancellation,
+                    Default::default(),
+                )
+                .await
+            }
+        });
+
+        // when
+        inference_tx.send(first_message).await?;
+        // wait for the first chunk, so there is something to persist
+        let _ = event_rx.recv().await;
+        cancellation.cancel();
+        while let Some(Event::Inference(message, _)) = event_rx.recv().await {
+            if message.interrupted {
+                break;
+            }
+        }
+        let second_message = db::create_message(&pool, Role::User, "are you sure?", 3).await?;
+        inference_tx.send(second_message).await?;
+        drop(inference_tx);
+        handle.await?;
+
+        // then
+        let messages = db::get_messages(&pool, 3).await?;
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1].role, Role::Assistant);
+        assert!(messages[1].interrupted);
+        assert!(!messages[1].content.is_empty());
+        assert!("Because of Rayleigh scattering".starts_with(&messages[1].content));
+        assert_eq!(messages[3].role, Role::Assistant);
+        assert!(!messages[3].interrupted);
+        assert_eq!(
+            messages[3].content,
+            "Sunlight is scattered by molecules of the air"
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_inference_stream_regenerate(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = Arc::new(MockBackend::echo());
+        let (inference_tx, inference_rx) = mpsc::channel(10);
+        let (event_tx, _event_rx) = mpsc::unbounded_channel();
+        let user_message = db::get_messages(&pool, 1).await?.remove(0);
+
+        // when
+        inference_tx.send(user_message).await?;
+        drop(inference_tx);
+        inference_stream(
+            pool.clone(),
+            inference_rx,
+            event_tx,
+            backend,
+            Default::default(),
+            Default::default(),
+        )
+        .await;
+
+        // then
+        let messages = db::get_messages(&pool, 1).await?;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].role, Role::Assistant);
+        assert_eq!(messages[1].content, "why is the sky blue?");
+        assert_eq!(messages[1].parent_id, Some(1));
+        let branches = db::list_branches(&pool, messages[1].id).await?;
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[0].content, "I don't know");
+
+        Ok(())
+    }
+
+    async fn infer(
+        pool: &SqlitePool,
+        backend: Arc<MockBackend>,
+        message: Message,
+        context_window: ContextWindow,
+    ) -> AppResult<()> {
+        let (inference_tx, inference_rx) = mpsc::channel(10);
+        let (event_tx, _event_rx) = mpsc::unbounded_channel();
+        inference_tx.send(message).await?;
+        drop(inference_tx);
+
+        inference_stream(
+            pool.clone(),
+            inference_rx,
+            event_tx,
+            backend,
+            Default::default(),
+            context_window,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_inference_stream_summarize(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = Arc::new(
+            MockBackend::canned(vec!["Warsaw".to_string(), "Berlin".to_string()])?
+                .with_completions(vec![
+                    "User asked for a joke".to_string(),
+                    "User asked for a joke and the capital of Poland".to_string(),
+                ])?,
+        );
+        // nothing but the last message fits into the context
+        let context_window = ContextWindow {
+            size: context::DEFAULT_RESPONSE_TOKENS + context::SUMMARY_TOKENS,
+            policy: ContextPolicy::Summarize,
+        };
+        let user_message = db::get_messages(&pool, 2).await?.remove(2);
+
+        // when
+        infer(&pool, backend.clone(), user_message, context_window).await?;
+        let user_message = db::create_message(&pool, Role::User, "and of Germany?", 2).await?;
+        infer(&pool, backend, user_message, context_window).await?;
+
+        // then
+        let messages = db::get_messages(&pool, 2).await?;
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[3].content, "Warsaw");
+        assert_eq!(messages[5].content, "Berlin");
+        assert!(messages[..5]
+            .iter()
+            .all(|message| message.token_count.is_some()));
+        // the first summary is extended with messages that weren't summarized yet
+        let summaries = db::get_summaries(&pool, 2).await?;
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(
+            summaries[0].content,
+            "User asked for a joke and the capital of Poland"
+        );
+        assert_eq!(summaries[0].parent_id, Some(messages[3].id));
+        assert_eq!(summaries[1].content, "User asked for a joke");
+        assert_eq!(summaries[1].parent_id, Some(4));
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_fit_into_context_moves_window(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = MockBackend::echo();
+        let conversation = db::get_conversation(&pool, 2).await?;
+        // the first turn doesn't fit anymore
+        let context_window = ContextWindow {
+            size: context::DEFAULT_RESPONSE_TOKENS + 10,
+            policy: ContextPolicy::KeepSystemPrompt,
+        };
+        let history = db::get_messages(&pool, 2).await?;
+
+        // when
+        let fitted =
+            fit_into_context(&pool, &backend, &conversation, &history, &context_window).await?;
+
+        // then
+        let (fitted_history, moved) = fitted.unwrap();
+        assert_eq!(fitted_history, history[2..]);
+        assert!(moved);
+
+        // given
+        db::create_message(&pool, Role::Assistant, "ok", 2).await?;
+        db::create_message(&pool, Role::User, "hi", 2).await?;
+        let history = db::get_messages(&pool, 2).await?;
+
+        // when
+        let fitted =
+            fit_into_context(&pool, &backend, &conversation, &history, &context_window).await?;
+
+        // then
+        // the same turn is dropped as before, backend's session is still valid
+        let (fitted_history, moved) = fitted.unwrap();
+        assert_eq!(fitted_history, history[2..]);
+        assert!(!moved);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_fit_into_context_long_summary(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = MockBackend::echo()
+            .with_completions(vec!["x".repeat(1000), "short summary".to_string()])?;
+        let conversation = db::get_conversation(&pool, 2).await?;
+        let context_window = ContextWindow {
+            size: context::DEFAULT_RESPONSE_TOKENS + context::SUMMARY_TOKENS + 10,
+            policy: ContextPolicy::Summarize,
+        };
+        db::create_message(&pool, Role::Assistant, "ok", 2).await?;
+        let user_message = db::create_message(&pool, Role::User, "hi", 2).await?;
+        let history = db::get_messages(&pool, 2).await?;
+
+        // when
+        let fitted =
+            fit_into_context(&pool, &backend, &conversation, &history, &context_window).await?;
+
+        // then
+        // summary of the first turn is too long, the second turn is summarized as well
+        let (fitted_history, moved) = fitted.unwrap();
+        assert_eq!(fitted_history.len(), 2);
+        assert_eq!(
+            fitted_history[0].content,
+            "Summary of the earlier part of the conversation:\nshort summary"
+        );
+        assert_eq!(fitted_history[1], user_message);
+        assert!(moved);
+        let summaries = db::get_summaries(&pool, 2).await?;
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].parent_id, Some(history[3].id));
+
+        // when
+        let fitted =
+            fit_into_context(&pool, &backend, &conversation, &history, &context_window).await?;
+
+        // then
+        // stored summary is reused, so the window stays where it was
+        let (refitted_history, moved) = fitted.unwrap();
+        assert_eq!(refitted_history, fitted_history);
+        assert!(!moved);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_inference_stream_names_conversation(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let backend = Arc::new(
+            MockBackend::canned(vec!["Because of Rayleigh scattering".to_string()])?
+                .with_completions(vec!["\"Sky colour\"\n".to_string()])?,
+        );
+        let (inference_tx, inference_rx) = mpsc::channel(10);
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let conversation = db::create_conversation(&pool, "", "~/.lokai/chats/5").await?;
+        let user_message =
+            db::create_message(&pool, Role::User, "why is the sky blue?", conversation.id).await?;
+
+        // when
+        inference_tx.send(user_message).await?;
+        drop(inference_tx);
+        inference_stream(
+            pool.clone(),
+            inference_rx,
+            event_tx,
+            backend,
+            Default::default(),
+            Default::default(),
+        )
+        .await;
+
+        // then
+        let renamed_conversation = db::get_conversation(&pool, conversation.id).await?;
+        assert_eq!(renamed_conversation.name, "Sky colour");
+        let mut renamed = None;
+        while let Ok(event) = event_rx.try_recv() {
+            if let Event::ConversationRenamed(conversation) = event {
+                renamed = Some(conversation);
+            }
+        }
+        assert_eq!(renamed, Some(renamed_conversation));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use futures::{stream, Stream, StreamExt};
+
+use crate::{backend::StreamError, AppResult};
+
+/// Splits streamed response body into lines, stream ends on the first transport error.
+pub fn lines<S, B>(body: S) -> impl Stream<Item = Result<String, StreamError>> + Send + 'static
+where
+    S: Stream<Item = reqwest::Result<B>> + Send + 'static,
+    B: AsRef<[u8]>,
+{
+    stream::unfold(Some((Box::pin(body), Vec::new())), |state| async move {
+        let (mut body, mut buffer) = state?;
+        loop {
+            if let Some(position) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line = buffer.drain(..=position).collect::<Vec<_>>();
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+
+                return Some((Ok(line), Some((body, buffer))));
+            }
+
+            match body.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(chunk.as_ref()),
+                Some(Err(err)) => {
+                    let err = StreamError(format!("cannot read response: {err}"));
+
+                    return Some((Err(err), None));
+                }
+                // last line doesn't have to end with new line character
+                None if !buffer.is_empty() => {
+                    let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+                    buffer.clear();
+
+                    return Some((Ok(line), Some((body, buffer))));
+                }
+                None => return None,
+            }
+        }
+    })
+}
+
+/// Turns unsuccessful response into an error with server's explanation, e.g. unknown model.
+pub async fn check_status(response: reqwest::Response) -> AppResult<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("server responded with {status}: {}", body.trim()).into())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+
+    /// Serves single HTTP request with given response body, returns server's URL
+    /// and a handle that resolves to the body of received request.
+    pub async fn stub_server(
+        content_type: &'static str,
+        response_body: &'static str,
+    ) -> (String, JoinHandle<String>) {
+        let (url, join_handle) =
+            stub_server_with_status("200 OK", content_type, response_body).await;
+        let join_handle = tokio::spawn(async move { join_handle.await.unwrap().1 });
+
+        (url, join_handle)
+    }
+
+    /// Serves single HTTP request with given status and response body, returns server's URL
+    /// and a handle that resolves to lowercased headers and the body of received request.
+    pub async fn stub_server_with_status(
+        status: &'static str,
+        content_type: &'static str,
+        response_body: &'static str,
+    ) -> (String, JoinHandle<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("cannot bind stub server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let join_handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.expect("cannot accept connection");
+
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            let body_start = loop {
+                let n = socket.read(&mut buffer).await.unwrap();
+                // client closed connection before it sent the whole request
+                if n == 0 {
+                    break request.len();
+                }
+                request.extend_from_slice(&buffer[..n]);
+                if let Some(position) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break position + 4;
+                }
+            };
+            let headers = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+            let content_length = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map(|value| value.trim().parse::<usize>().unwrap())
+                .unwrap_or_default();
+            while request.len() < body_start + content_length {
+                let n = socket.read(&mut buffer).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..n]);
+            }
+
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{response_body}",
+                response_body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.unwrap();
+
+            (
+                headers,
+                String::from_utf8_lossy(&request[body_start..]).to_string(),
+            )
+        });
+
+        (url, join_handle)
+    }
+
+    #[tokio::test]
+    async fn test_lines() {
+        // given
+        let chunks: Vec<reqwest::Result<&str>> = vec![
+            Ok("first "),
+            Ok("line\nsecond line\r\n"),
+            Ok("\nthi"),
+            Ok("rd line"),
+        ];
+
+        // when
+        let lines = lines(stream::iter(chunks)).collect::<Vec<_>>().await;
+
+        // then
+        assert_eq!(
+            lines,
+            vec![
+                Ok("first line".to_string()),
+                Ok("second line".to_string()),
+                Ok("".to_string()),
+                Ok("third line".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::{channel::mpsc, future::BoxFuture, FutureExt, StreamExt};
+use kalosm::language::{
+    Chat, ChatBuilder, ChatHistoryItem, FileSource, GenerationParameters, Llama, LlamaSource,
+    MessageType, Model, Tokenizer,
+};
+use kalosm_language::kalosm_llama::Cache;
+use tokio::sync::Mutex;
+
+use crate::{
+    backend::{
+        cancellable,
+        session_cache::{CachedSession, SessionCache},
+        Cancellation, InferenceBackend, InferenceRequest, ModelInfo, TextStream,
+    },
+    context,
+    models::{Conversation, GenerationSettings, Message, Role},
+    AppResult,
+};
+
+pub const DEFAULT_MODEL: &str = "llama-3.1-8b-chat";
+
+/// Models built into kalosm, all of them are quantized to 4 bits.
+pub const MODELS: &[(&str, fn() -> LlamaSource)] = &[
+    ("llama-3.1-8b-chat", LlamaSource::llama_3_1_8b_chat),
+    ("llama-3.2-1b-chat", LlamaSource::llama_3_2_1b_chat),
+    ("llama-3.2-3b-chat", LlamaSource::llama_3_2_3b_chat),
+    ("llama-3-8b-chat", LlamaSource::llama_3_8b_chat),
+    ("mistral-7b-instruct-2", LlamaSource::mistral_7b_instruct_2),
+    (
+        "phi-3.5-mini-4k-instruct",
+        LlamaSource::phi_3_5_mini_4k_instruct,
+    ),
+    ("qwen-2.5-7b-instruct", LlamaSource::qwen_2_5_7b_instruct),
+    ("tiny-llama-1.1b-chat", LlamaSource::tiny_llama_1_1b_chat),
+];
+
+/// Returns source of a built-in model or of a local GGUF file, the latter requires tokenizer.
+pub fn llama_source(model: &str, tokenizer: Option<PathBuf>) -> AppResult<LlamaSource> {
+    if let Some((_, source)) = MODELS.iter().find(|(name, _)| *name == model) {
+        return Ok(source());
+    }
+
+    if model.ends_with(".gguf") {
+        let tokenizer = tokenizer.ok_or("tokenizer is required by local GGUF model")?;
+        let source = LlamaSource::new(FileSource::local(PathBuf::from(model)))
+            .with_tokenizer(FileSource::local(tokenizer));
+
+        return Ok(source);
+    }
+
+    let models = MODELS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Err(format!("Expected one of [{models}] or path to GGUF file, got [{model}]").into())
+}
+
+/// Tells whether model can only be run by llama backend, other backends expect names known to
+/// their servers.
+pub fn is_llama_model(model: &str) -> bool {
+    MODELS.iter().any(|(name, _)| *name == model) || model.ends_with(".gguf")
+}
+
+/// Tokenizer of local GGUF model chosen for a conversation, it's expected next to the model file.
+fn local_tokenizer(model: &str) -> Option<PathBuf> {
+    model
+        .ends_with(".gguf")
+        .then(|| Path::new(model).with_file_name("tokenizer.json"))
+}
+
+pub struct LlamaBackend {
+    /// Models loaded so far, other than default model they're loaded when conversation requires them
+    models: Mutex<HashMap<String, Llama>>,
+    model_name: String,
+    /// Tokenizer of the default model, it's used to count tokens of all conversations
+    tokenizer: Arc<dyn Tokenizer + Send + Sync>,
+    kalosm_cache: Cache,
+    /// Chats of recently active conversations
+    sessions: Arc<Mutex<SessionCache<Chat>>>,
+    cancellation: Cancellation,
+}
+
+impl LlamaBackend {
+    pub fn new(
+        llama: Llama,
+        model_name: impl Into<String>,
+        kalosm_cache: Cache,
+        sessions: Arc<Mutex<SessionCache<Chat>>>,
+    ) -> Self {
+        let model_name = model_name.into();
+        let tokenizer = llama.tokenizer();
+
+        Self {
+            models: Mutex::new(HashMap::from([(model_name.clone(), llama)])),
+            model_name,
+            tokenizer,
+            kalosm_cache,
+            sessions,
+            cancellation: Default::default(),
+        }
+    }
+
+    async fn llama(&self, model_name: Option<&str>) -> AppResult<Llama> {
+        let model_name = model_name.unwrap_or(&self.model_name);
+
+        let mut models = self.models.lock().await;
+        if let Some(llama) = models.get(model_name) {
+            return Ok(llama.clone());
+        }
+
+        tracing::info!("loading model: {}", model_name);
+        let source = llama_source(model_name, local_tokenizer(model_name))?
+            .with_cache(self.kalosm_cache.clone());
+        let llama = Llama::builder().with_source(source).build().await?;
+        models.insert(model_name.to_string(), llama.clone());
+
+        Ok(llama)
+    }
+
+    async fn chat_builder(
+        &self,
+        conversation: &Conversation,
+        history: &[Message],
+    ) -> AppResult<ChatBuilder> {
+        let settings = &conversation.settings;
+        let llama = self.llama(settings.model.as_deref()).await?;
+        let mut chat_builder = Chat::builder(llama).with_sampler(generation_parameters(settings));
+        // system prompt that is already stored in the session takes precedence,
+        // this one is applied when session is created from scratch
+        let system_prompt = history
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .map(|message| message.content.as_str())
+            .collect::<Vec<_>>();
+        if !system_prompt.is_empty() {
+            chat_builder = chat_builder.with_system_prompt(system_prompt.join("\n\n"));
+        }
+
+        Ok(chat_builder)
+    }
+
+    /// Loads chat from conversation's session file, history is replayed when the file is missing
+    /// or kalosm cannot load it (e.g. it's corrupt or was saved by incompatible version).
+    async fn load_chat(&self, conversation: &Conversation, history: &[Message]) -> AppResult<Chat> {
+        let chat = self
+            .chat_builder(conversation, history)
+            .await?
+            .with_try_session_path(&conversation.session_path)
+            .build();
+
+        // kalosm silently starts from scratch when session cannot be loaded
+        let turns = history
+            .iter()
+            .filter(|message| message.role != Role::System)
+            .count();
+        if chat.history().len() >= turns {
+            return Ok(chat);
+        }
+
+        tracing::warn!(
+            "session {} is missing or cannot be loaded, replaying {} messages",
+            conversation.session_path,
+            turns
+        );
+        self.replay_chat(conversation, history).await
+    }
+
+    /// Creates chat from scratch that knows given history.
+    async fn replay_chat(
+        &self,
+        conversation: &Conversation,
+        history: &[Message],
+    ) -> AppResult<Chat> {
+        let chat = self
+            .chat_builder(conversation, history)
+            .await?
+            .with_initial_history(chat_history(history))
+            .build();
+
+        Ok(chat)
+    }
+}
+
+fn generation_parameters(settings: &GenerationSettings) -> GenerationParameters {
+    let mut parameters = GenerationParameters::default();
+    if let Some(temperature) = settings.temperature {
+        parameters = parameters.with_temperature(temperature);
+    }
+    if let Some(top_p) = settings.top_p {
+        parameters = parameters.with_top_p(top_p as f64);
+    }
+    if let Some(max_tokens) = settings.max_tokens {
+        parameters = parameters.with_max_length(max_tokens);
+    }
+    if let Some(seed) = settings.seed {
+        parameters = parameters.with_seed(seed as u64);
+    }
+
+    parameters
+}
+
+/// Converts conversation's history into kalosm's chat history, system prompt is set separately.
+fn chat_history(history: &[Message]) -> Vec<ChatHistoryItem> {
+    history
+        .iter()
+        .filter_map(|message| match message.role {
+            Role::Assistant => Some(ChatHistoryItem::new(
+                MessageType::ModelAnswer,
+                &message.content,
+            )),
+            Role::System => None,
+            Role::User => Some(ChatHistoryItem::new(
+                MessageType::UserMessage,
+                &message.content,
+            )),
+        })
+        .collect()
+}
+
+impl InferenceBackend for LlamaBackend {
+    fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            backend: "kalosm".to_string(),
+            name: self.model_name.clone(),
+        }
+    }
+
+    fn validate_model(&self, model: &str) -> AppResult<()> {
+        // default model is already loaded
+        if model == self.model_name {
+            return Ok(());
+        }
+
+        let tokenizer = local_tokenizer(model);
+        if let Some(tokenizer) = &tokenizer {
+            if !Path::new(model).is_file() {
+                return Err(format!("model file doesn't exist: {model}").into());
+            }
+            if !tokenizer.is_file() {
+                return Err(format!(
+                    "local GGUF model requires tokenizer: {}",
+                    tokenizer.display()
+                )
+                .into());
+            }
+        }
+
+        llama_source(model, tokenizer).map(|_| ())
+    }
+
+    fn stream(&self, request: InferenceRequest) -> BoxFuture<'_, AppResult<TextStream>> {
+        async move {
+            let prompt = request
+                .last_message()
+                .map(|message| message.content.clone())
+                .ok_or("cannot run inference on empty conversation")?;
+            let conversation_id = request.conversation.id;
+            let settings = request.conversation.settings.clone();
+            let session_path = request.conversation.session_path.clone();
+
+            let cached = self.sessions.lock().await.take(conversation_id);
+            let cached = match cached {
+                // session gets replaced with the one replayed from history
+                Some(_) if request.rebuild_session => None,
+                // settings are baked into the chat, it's loaded again with the new ones
+                Some(mut cached) if cached.settings != settings => {
+                    cached.save().await;
+                    None
+                }
+                cached => cached,
+            };
+            let mut cached = match cached {
+                Some(cached) => cached,
+                None => {
+                    let previous_turns = &request.history[..request.history.len() - 1];
+                    let chat = if request.rebuild_session {
+                        // stored session contains turns that are no longer part of the conversation,
+                        // the session is replayed from history and overwritten once it's saved
+                        self.replay_chat(&request.conversation, previous_turns)
+                            .await?
+                    } else {
+                        self.load_chat(&request.conversation, previous_turns)
+                            .await?
+                    };
+
+                    CachedSession::new(chat, session_path, settings)
+                }
+            };
+
+            let mut text_stream = cancellable(
+                cached.session.add_message(prompt),
+                self.cancellation.renew(),
+            );
+            cached.dirty = true;
+
+            let (chunk_tx, chunk_rx) = mpsc::unbounded();
+            let sessions = self.sessions.clone();
+            tokio::spawn(async move {
+                while let Some(chunk) = text_stream.next().await {
+                    if chunk_tx.unbounded_send(chunk).is_err() {
+                        break;
+                    }
+                }
+
+                // session is saved to disk once it gets evicted from the cache
+                sessions.lock().await.insert(conversation_id, cached).await;
+                // stream is finished once session is back in the cache, so next message uses up to date session
+                drop(chunk_tx);
+            });
+
+            Ok(chunk_rx.map(Ok).boxed())
+        }
+        .boxed()
+    }
+
+    fn rebuild_session(
+        &self,
+        conversation: Conversation,
+        history: Vec<Message>,
+    ) -> BoxFuture<'_, AppResult<()>> {
+        async move {
+            // cached chat would overwrite rebuilt session once it's evicted
+            drop(self.sessions.lock().await.take(conversation.id));
+
+            let chat = self.replay_chat(&conversation, &history).await?;
+            chat.save_session(&conversation.session_path).await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer
+            .encode(text, false)
+            .map(|tokens| tokens.len())
+            .unwrap_or_else(|_| context::estimate_tokens(text))
+    }
+
+    fn complete(
+        &self,
+        conversation: Conversation,
+        history: Vec<Message>,
+    ) -> BoxFuture<'_, AppResult<String>> {
+        async move {
+            let (prompt, previous_turns) = history
+                .split_last()
+                .ok_or("cannot run inference on empty conversation")?;
+            let mut chat = self.replay_chat(&conversation, previous_turns).await?;
+            let text_stream =
+                cancellable(chat.add_message(&prompt.content), self.cancellation.renew());
+
+            Ok(text_stream.collect::<String>().await)
+        }
+        .boxed()
+    }
+
+    fn forget(&self, conversation: Conversation) -> BoxFuture<'_, ()> {
+        async move {
+            self.sessions.lock().await.forget(conversation.id);
+        }
+        .boxed()
+    }
+
+    fn flush(&self) -> BoxFuture<'_, AppResult<()>> {
+        async move {
+            self.sessions.lock().await.flush().await;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_llama_source() {
+        assert!(llama_source("llama-3.2-1b-chat", None).is_ok());
+        assert!(llama_source("/models/model.gguf", Some("/models/tokenizer.json".into())).is_ok());
+        assert!(llama_source("/models/model.gguf", None).is_err());
+        assert!(llama_source("gpt-4", None).is_err());
+    }
+
+    #[test]
+    fn test_local_tokenizer() {
+        assert_eq!(
+            local_tokenizer("/models/model.gguf"),
+            Some(PathBuf::from("/models/tokenizer.json"))
+        );
+        assert_eq!(local_tokenizer("llama-3.2-1b-chat"), None);
+    }
+}
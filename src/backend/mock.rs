@@ -0,0 +1,285 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, stream, FutureExt, StreamExt};
+
+use crate::{
+    backend::{
+        cancellable, Cancellation, InferenceBackend, InferenceRequest, ModelInfo, TextStream,
+    },
+    models::{Conversation, Message},
+    AppResult,
+};
+
+pub const DEFAULT_TOKEN_DELAY_MS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockReplies {
+    /// Replies with the content of the last message
+    Echo,
+    /// Replies with consecutive canned responses, starts over once all of them are used
+    Canned(Vec<String>),
+}
+
+impl MockReplies {
+    fn canned(replies: Vec<String>) -> AppResult<Self> {
+        if replies.is_empty() {
+            return Err("Mock backend requires at least one canned reply".into());
+        }
+
+        Ok(Self::Canned(replies))
+    }
+
+    /// Returns reply to given history, `counter` keeps position among canned replies.
+    fn next(&self, counter: &AtomicUsize, history: &[Message]) -> String {
+        match self {
+            MockReplies::Echo => history
+                .last()
+                .map(|message| message.content.clone())
+                .unwrap_or_default(),
+            MockReplies::Canned(replies) => {
+                let index = counter.fetch_add(1, Ordering::SeqCst) % replies.len();
+                replies[index].clone()
+            }
+        }
+    }
+}
+
+/// Deterministic backend that doesn't require any model, used by tests and offline demos.
+pub struct MockBackend {
+    replies: MockReplies,
+    next_reply: AtomicUsize,
+    /// Replies to completions (e.g. summaries and titles), they're independent of chat responses
+    completions: MockReplies,
+    next_completion: AtomicUsize,
+    token_delay: Duration,
+    /// Conversations whose sessions backend was asked to rebuild, in order
+    rebuilt_sessions: Mutex<Vec<u32>>,
+    cancellation: Cancellation,
+}
+
+impl MockBackend {
+    pub fn echo() -> Self {
+        Self::new(MockReplies::Echo)
+    }
+
+    pub fn canned(replies: Vec<String>) -> AppResult<Self> {
+        Ok(Self::new(MockReplies::canned(replies)?))
+    }
+
+    /// Replaces echoed completions with consecutive canned ones.
+    pub fn with_completions(mut self, completions: Vec<String>) -> AppResult<Self> {
+        self.completions = MockReplies::canned(completions)?;
+        Ok(self)
+    }
+
+    /// Loads canned replies from JSON file containing list of strings.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> AppResult<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let replies: Vec<String> = serde_json::from_str(&content)?;
+
+        Self::canned(replies)
+    }
+
+    pub fn with_token_delay(mut self, token_delay: Duration) -> Self {
+        self.token_delay = token_delay;
+        self
+    }
+
+    fn new(replies: MockReplies) -> Self {
+        Self {
+            replies,
+            next_reply: AtomicUsize::new(0),
+            completions: MockReplies::Echo,
+            next_completion: AtomicUsize::new(0),
+            token_delay: Duration::ZERO,
+            rebuilt_sessions: Default::default(),
+            cancellation: Default::default(),
+        }
+    }
+
+    pub fn rebuilt_sessions(&self) -> Vec<u32> {
+        self.rebuilt_sessions.lock().unwrap().clone()
+    }
+}
+
+impl InferenceBackend for MockBackend {
+    fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            backend: "mock".to_string(),
+            name: match self.replies {
+                MockReplies::Echo => "echo".to_string(),
+                MockReplies::Canned(_) => "canned".to_string(),
+            },
+        }
+    }
+
+    fn stream(&self, request: InferenceRequest) -> BoxFuture<'_, AppResult<TextStream>> {
+        let tokens = tokenize(&self.replies.next(&self.next_reply, &request.history));
+        let token_delay = self.token_delay;
+        let text_stream = stream::iter(tokens).then(move |token| async move {
+            tokio::time::sleep(token_delay).await;
+            Ok(token)
+        });
+
+        let text_stream = cancellable(text_stream, self.cancellation.renew());
+
+        async move { Ok(text_stream) }.boxed()
+    }
+
+    fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    fn complete(
+        &self,
+        _conversation: Conversation,
+        history: Vec<Message>,
+    ) -> BoxFuture<'_, AppResult<String>> {
+        let completion = self.completions.next(&self.next_completion, &history);
+
+        async move { Ok(completion) }.boxed()
+    }
+
+    fn rebuild_session(
+        &self,
+        conversation: Conversation,
+        _history: Vec<Message>,
+    ) -> BoxFuture<'_, AppResult<()>> {
+        self.rebuilt_sessions.lock().unwrap().push(conversation.id);
+
+        async { Ok(()) }.boxed()
+    }
+}
+
+/// Splits text into word-sized chunks that together give back the original text.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split_inclusive(char::is_whitespace)
+        .map(|token| token.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::models::Role;
+
+    fn request(prompt: &str) -> InferenceRequest {
+        InferenceRequest {
+            conversation: Conversation::new_test(1, "conversation 1"),
+            history: vec![Message::new_test(1, Role::User, prompt)],
+            rebuild_session: false,
+        }
+    }
+
+    #[rstest]
+    #[case("", vec![])]
+    #[case("Hello", vec!["Hello"])]
+    #[case("Hello, World!", vec!["Hello, ", "World!"])]
+    #[case("Hello\nWorld ", vec!["Hello\n", "World "])]
+    fn test_tokenize(#[case] text: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(tokenize(text), expected);
+    }
+
+    #[tokio::test]
+    async fn test_echo() -> AppResult<()> {
+        // given
+        let backend = MockBackend::echo();
+
+        // when
+        let response = backend
+            .stream(request("why is the sky blue?"))
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // then
+        assert_eq!(response, vec!["why ", "is ", "the ", "sky ", "blue?"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_canned_replies_start_over() -> AppResult<()> {
+        // given
+        let backend = MockBackend::canned(vec!["first".to_string(), "second".to_string()])?;
+
+        // when
+        let mut responses = vec![];
+        for _ in 0..3 {
+            let response = backend
+                .stream(request("why is the sky blue?"))
+                .await?
+                .try_collect::<String>()
+                .await?;
+            responses.push(response);
+        }
+
+        // then
+        assert_eq!(responses, vec!["first", "second", "first"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canned_without_replies() {
+        assert!(MockBackend::canned(vec![]).is_err());
+        assert!(MockBackend::echo().with_completions(vec![]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_completions_dont_take_replies() -> AppResult<()> {
+        // given
+        let backend = MockBackend::canned(vec!["first".to_string(), "second".to_string()])?
+            .with_completions(vec!["summary".to_string()])?;
+        let conversation = Conversation::new_test(1, "conversation 1");
+        let history = vec![Message::new_test(1, Role::User, "why is the sky blue?")];
+
+        // when
+        let first = backend
+            .stream(request("why is the sky blue?"))
+            .await?
+            .try_collect::<String>()
+            .await?;
+        let completion = backend.complete(conversation, history).await?;
+        let second = backend
+            .stream(request("why is the sky blue?"))
+            .await?
+            .try_collect::<String>()
+            .await?;
+
+        // then
+        assert_eq!(
+            (first.as_str(), completion.as_str(), second.as_str()),
+            ("first", "summary", "second")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel() -> AppResult<()> {
+        // given
+        let backend = MockBackend::echo().with_token_delay(Duration::from_millis(10));
+        let mut text_stream = backend.stream(request("why is the sky blue?")).await?;
+        let first_token = text_stream.next().await;
+
+        // when
+        backend.cancel();
+
+        // then
+        assert_eq!(first_token, Some(Ok("why ".to_string())));
+        assert_eq!(text_stream.next().await, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use futures::{future::BoxFuture, stream::BoxStream, FutureExt, Stream, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    context,
+    models::{Conversation, Message, Role},
+    AppResult,
+};
+
+pub mod http;
+pub mod llama;
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+pub mod session_cache;
+
+/// Chunks of generated response, stream ends after the first error.
+pub type TextStream = BoxStream<'static, Result<String, StreamError>>;
+
+/// Failure reported in the middle of a response, e.g. server sent an error instead of the next chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamError(pub String);
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Local model run by kalosm
+    Llama,
+    /// Echoes prompts or replies with canned responses, doesn't require any model
+    Mock,
+    /// Ollama server
+    Ollama,
+    /// Any server compatible with OpenAI's chat completions API (llama.cpp server, vLLM, LM Studio)
+    Openai,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub backend: String,
+    pub name: String,
+}
+
+/// Everything backend needs to answer the last message of a conversation.
+#[derive(Debug, Clone)]
+pub struct InferenceRequest {
+    pub conversation: Conversation,
+    /// Full history of a conversation, the last element is a message that requires response.
+    pub history: Vec<Message>,
+    /// History has changed since backend's last response (e.g. response was regenerated),
+    /// backends that keep their own state have to rebuild it from `history`.
+    pub rebuild_session: bool,
+}
+
+impl InferenceRequest {
+    pub fn last_message(&self) -> Option<&Message> {
+        self.history.last()
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.history
+            .iter()
+            .find(|message| message.role == Role::System)
+            .map(|message| message.content.as_str())
+    }
+}
+
+pub trait InferenceBackend: Send + Sync {
+    fn model_info(&self) -> ModelInfo;
+
+    /// Checks whether model chosen for a conversation can be used by the backend.
+    fn validate_model(&self, _model: &str) -> AppResult<()> {
+        Ok(())
+    }
+
+    /// Streams assistant's response. Stream ends when model stops generating or when
+    /// generation gets cancelled.
+    fn stream(&self, request: InferenceRequest) -> BoxFuture<'_, AppResult<TextStream>>;
+
+    /// Cancels currently generated response, it's noop when nothing is generated.
+    fn cancel(&self);
+
+    /// Counts tokens of given text, backends without access to model's tokenizer estimate it.
+    fn count_tokens(&self, text: &str) -> usize {
+        context::estimate_tokens(text)
+    }
+
+    /// Generates response to history that isn't part of any conversation (e.g. a summary),
+    /// conversation only provides generation settings and its session stays untouched.
+    fn complete(
+        &self,
+        conversation: Conversation,
+        history: Vec<Message>,
+    ) -> BoxFuture<'_, AppResult<String>> {
+        async move {
+            let text_stream = self
+                .stream(InferenceRequest {
+                    conversation,
+                    history,
+                    rebuild_session: true,
+                })
+                .await?;
+
+            Ok(text_stream.try_collect::<String>().await?)
+        }
+        .boxed()
+    }
+
+    /// Replaces conversation's session kept by backend with the one replayed from history,
+    /// it's noop for backends that receive full history with every request.
+    fn rebuild_session(
+        &self,
+        _conversation: Conversation,
+        _history: Vec<Message>,
+    ) -> BoxFuture<'_, AppResult<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    /// Drops state kept for permanently deleted conversation, so nothing is written to its
+    /// session file anymore.
+    fn forget(&self, _conversation: Conversation) -> BoxFuture<'_, ()> {
+        async {}.boxed()
+    }
+
+    /// Persists state that backend keeps in memory, it's called before application exits.
+    fn flush(&self) -> BoxFuture<'_, AppResult<()>> {
+        async { Ok(()) }.boxed()
+    }
+}
+
+/// Cancellation token shared between backend and the stream it produces.
+#[derive(Debug, Default)]
+pub struct Cancellation(Mutex<CancellationToken>);
+
+impl Cancellation {
+    /// Replaces the token, so new generation doesn't inherit previous cancellation.
+    pub fn renew(&self) -> CancellationToken {
+        let mut token = self.0.lock().expect("cancellation lock poisoned");
+        *token = CancellationToken::new();
+        token.clone()
+    }
+
+    pub fn cancel(&self) {
+        self.0.lock().expect("cancellation lock poisoned").cancel();
+    }
+}
+
+/// Ends the stream as soon as the token gets cancelled.
+pub fn cancellable<S, T>(stream: S, token: CancellationToken) -> BoxStream<'static, T>
+where
+    S: Stream<Item = T> + Send + 'static,
+{
+    stream.take_until(token.cancelled_owned()).boxed()
+}
@@ -0,0 +1,314 @@
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{
+        cancellable, http, llama, Cancellation, InferenceBackend, InferenceRequest, ModelInfo,
+        StreamError, TextStream,
+    },
+    models::Role,
+    AppResult,
+};
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+pub const DEFAULT_MODEL: &str = "llama3.1";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ChatMessage {
+    role: Role,
+    content: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    options: ChatOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatResponseChunk {
+    message: Option<ChatMessage>,
+    error: Option<String>,
+}
+
+/// Backend that talks to Ollama's `/api/chat` streaming endpoint.
+pub struct OllamaBackend {
+    client: reqwest::Client,
+    base_url: String,
+    model: String,
+    cancellation: Cancellation,
+}
+
+impl OllamaBackend {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into(),
+            model: model.into(),
+            cancellation: Default::default(),
+        }
+    }
+
+    fn chat_url(&self) -> String {
+        format!("{}/api/chat", self.base_url.trim_end_matches('/'))
+    }
+}
+
+impl InferenceBackend for OllamaBackend {
+    fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            backend: "ollama".to_string(),
+            name: self.model.clone(),
+        }
+    }
+
+    fn validate_model(&self, model: &str) -> AppResult<()> {
+        match llama::is_llama_model(model) {
+            true => Err(format!(
+                "{model} requires llama backend, expected name of a model pulled by Ollama"
+            )
+            .into()),
+            false => Ok(()),
+        }
+    }
+
+    fn stream(&self, request: InferenceRequest) -> BoxFuture<'_, AppResult<TextStream>> {
+        let settings = request.conversation.settings;
+        let chat_request = ChatRequest {
+            model: settings.model.unwrap_or(self.model.clone()),
+            messages: request
+                .history
+                .into_iter()
+                .map(|message| ChatMessage {
+                    role: message.role,
+                    content: message.content,
+                })
+                .collect(),
+            stream: true,
+            options: ChatOptions {
+                temperature: settings.temperature,
+                top_p: settings.top_p,
+                num_predict: settings.max_tokens,
+                seed: settings.seed,
+            },
+        };
+
+        async move {
+            let response = self
+                .client
+                .post(self.chat_url())
+                .json(&chat_request)
+                .send()
+                .await
+                .map_err(|err| format!("cannot connect to Ollama at {}: {}", self.base_url, err))?;
+            let response = http::check_status(response).await?;
+
+            let text_stream = http::lines(response.bytes_stream()).filter_map(|line| async move {
+                match line {
+                    Ok(line) => parse_chunk(&line),
+                    Err(err) => Some(Err(err)),
+                }
+            });
+
+            Ok(cancellable(text_stream, self.cancellation.renew()))
+        }
+        .boxed()
+    }
+
+    fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+}
+
+/// Extracts content from a single line of Ollama's newline delimited JSON response.
+fn parse_chunk(line: &str) -> Option<Result<String, StreamError>> {
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    match serde_json::from_str::<ChatResponseChunk>(line) {
+        Ok(ChatResponseChunk {
+            error: Some(err), ..
+        }) => Some(Err(StreamError(format!("Ollama returned an error: {err}")))),
+        Ok(ChatResponseChunk { message, .. }) => message
+            .map(|message| message.content)
+            .filter(|content| !content.is_empty())
+            .map(Ok),
+        Err(err) => {
+            tracing::error!("Cannot parse Ollama response {:?}: {}", line, err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::{
+        backend::http::tests::stub_server,
+        models::{Conversation, GenerationSettings, Message},
+    };
+
+    #[rstest]
+    #[case(
+        r#"{"model":"llama3.1","message":{"role":"assistant","content":"The"},"done":false}"#,
+        Some(Ok("The"))
+    )]
+    #[case(
+        r#"{"model":"llama3.1","message":{"role":"assistant","content":""},"done":true}"#,
+        None
+    )]
+    #[case(
+        r#"{"error":"model 'llama3.1' not found"}"#,
+        Some(Err("Ollama returned an error: model 'llama3.1' not found"))
+    )]
+    #[case("", None)]
+    #[case("not a json", None)]
+    fn test_parse_chunk(#[case] line: &str, #[case] expected: Option<Result<&str, &str>>) {
+        assert_eq!(
+            parse_chunk(line),
+            expected.map(|result| result
+                .map(|s| s.to_string())
+                .map_err(|err| StreamError(err.to_string())))
+        );
+    }
+
+    fn request() -> InferenceRequest {
+        InferenceRequest {
+            conversation: Conversation {
+                settings: GenerationSettings {
+                    top_p: Some(0.5),
+                    seed: Some(42),
+                    ..Default::default()
+                },
+                ..Conversation::new_test(1, "conversation 1")
+            },
+            history: vec![
+                Message::new_test(1, Role::System, "You are a physicist"),
+                Message::new_test(2, Role::User, "why is the sky blue?"),
+            ],
+            rebuild_session: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream() -> AppResult<()> {
+        // given
+        let (url, request_body) = stub_server(
+            "application/x-ndjson",
+            concat!(
+                r#"{"model":"llama3.1","message":{"role":"assistant","content":"Because of "},"done":false}"#,
+                "\n",
+                r#"{"model":"llama3.1","message":{"role":"assistant","content":"Rayleigh scattering"},"done":false}"#,
+                "\n",
+                r#"{"model":"llama3.1","message":{"role":"assistant","content":""},"done":true}"#,
+                "\n",
+            ),
+        )
+        .await;
+        let backend = OllamaBackend::new(url, "llama3.1");
+        let request = request();
+
+        // when
+        let response = backend
+            .stream(request)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // then
+        assert_eq!(response, vec!["Because of ", "Rayleigh scattering"]);
+
+        let request_body: serde_json::Value = serde_json::from_str(&request_body.await?)?;
+        assert_eq!(
+            request_body,
+            serde_json::json!({
+                "model": "llama3.1",
+                "messages": [
+                    {"role": "system", "content": "You are a physicist"},
+                    {"role": "user", "content": "why is the sky blue?"},
+                ],
+                "stream": true,
+                "options": {"top_p": 0.5, "seed": 42},
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_error() -> AppResult<()> {
+        // given
+        let (url, _request_body) = stub_server(
+            "application/x-ndjson",
+            concat!(
+                r#"{"model":"llama3.1","message":{"role":"assistant","content":"Because "},"done":false}"#,
+                "\n",
+                r#"{"error":"model runner has unexpectedly stopped"}"#,
+                "\n",
+            ),
+        )
+        .await;
+        let backend = OllamaBackend::new(url, "llama3.1");
+
+        // when
+        let response = backend.stream(request()).await?.collect::<Vec<_>>().await;
+
+        // then
+        assert_eq!(
+            response,
+            vec![
+                Ok("Because ".to_string()),
+                Err(StreamError(
+                    "Ollama returned an error: model runner has unexpectedly stopped".to_string()
+                ))
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_server_not_running() -> AppResult<()> {
+        // given
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        drop(listener);
+        let backend = OllamaBackend::new(url.clone(), "llama3.1");
+
+        // when
+        let result = backend.stream(request()).await;
+
+        // then
+        let err = result.err().map(|err| err.to_string()).unwrap_or_default();
+        assert!(err.starts_with(&format!("cannot connect to Ollama at {url}")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_model() {
+        let backend = OllamaBackend::new(DEFAULT_BASE_URL, DEFAULT_MODEL);
+
+        assert!(backend.validate_model("llama3.2:1b").is_ok());
+        assert!(backend.validate_model("llama-3.2-1b-chat").is_err());
+        assert!(backend.validate_model("/models/model.gguf").is_err());
+    }
+}
@@ -0,0 +1,400 @@
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{
+        cancellable, http, llama, Cancellation, InferenceBackend, InferenceRequest, ModelInfo,
+        StreamError, TextStream,
+    },
+    models::Role,
+    AppResult,
+};
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:8080/v1";
+pub const DEFAULT_MODEL: &str = "default";
+
+const DONE_EVENT: &str = "[DONE]";
+
+#[derive(Serialize, Debug)]
+struct ChatMessage {
+    role: Role,
+    content: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    /// Servers report failures in the middle of the stream with an error event
+    error: Option<ChunkError>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkError {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkChoice {
+    delta: ChunkDelta,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkDelta {
+    content: Option<String>,
+}
+
+/// Backend that streams from any server speaking OpenAI's `/chat/completions` protocol,
+/// e.g. llama.cpp server, vLLM or LM Studio.
+pub struct OpenAIBackend {
+    client: reqwest::Client,
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    cancellation: Cancellation,
+}
+
+impl OpenAIBackend {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into(),
+            model: model.into(),
+            api_key: None,
+            cancellation: Default::default(),
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    fn chat_completions_url(&self) -> String {
+        format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
+    }
+}
+
+impl InferenceBackend for OpenAIBackend {
+    fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            backend: "openai".to_string(),
+            name: self.model.clone(),
+        }
+    }
+
+    fn validate_model(&self, model: &str) -> AppResult<()> {
+        match llama::is_llama_model(model) {
+            true => Err(format!(
+                "{model} requires llama backend, expected name of a model served by the server"
+            )
+            .into()),
+            false => Ok(()),
+        }
+    }
+
+    fn stream(&self, request: InferenceRequest) -> BoxFuture<'_, AppResult<TextStream>> {
+        let settings = request.conversation.settings;
+        let chat_request = ChatCompletionRequest {
+            model: settings.model.unwrap_or(self.model.clone()),
+            messages: request
+                .history
+                .into_iter()
+                .map(|message| ChatMessage {
+                    role: message.role,
+                    content: message.content,
+                })
+                .collect(),
+            stream: true,
+            temperature: settings.temperature,
+            top_p: settings.top_p,
+            max_tokens: settings.max_tokens,
+            seed: settings.seed,
+        };
+
+        async move {
+            let mut request_builder = self
+                .client
+                .post(self.chat_completions_url())
+                .json(&chat_request);
+            if let Some(api_key) = &self.api_key {
+                request_builder = request_builder.bearer_auth(api_key);
+            }
+            let response = request_builder
+                .send()
+                .await
+                .map_err(|err| format!("cannot connect to {}: {}", self.base_url, err))?;
+            let response = http::check_status(response).await?;
+
+            let text_stream = http::lines(response.bytes_stream())
+                .take_while(|line| {
+                    let done = line.as_deref().ok().and_then(parse_event_data) == Some(DONE_EVENT);
+                    async move { !done }
+                })
+                .filter_map(|line| async move {
+                    match line {
+                        Ok(line) => parse_event(&line),
+                        Err(err) => Some(Err(err)),
+                    }
+                });
+
+            Ok(cancellable(text_stream, self.cancellation.renew()))
+        }
+        .boxed()
+    }
+
+    fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+}
+
+/// Returns data of server-sent event line, other fields (event, id, comments) are ignored.
+fn parse_event_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(|data| data.trim())
+}
+
+/// Extracts content delta from a single server-sent event line.
+fn parse_event(line: &str) -> Option<Result<String, StreamError>> {
+    let data = parse_event_data(line).filter(|data| !data.is_empty() && *data != DONE_EVENT)?;
+
+    match serde_json::from_str::<ChatCompletionChunk>(data) {
+        Ok(ChatCompletionChunk {
+            error: Some(err), ..
+        }) => Some(Err(StreamError(format!(
+            "server returned an error: {}",
+            err.message
+        )))),
+        Ok(chunk) => chunk
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.delta.content)
+            .filter(|content| !content.is_empty())
+            .map(Ok),
+        Err(err) => {
+            tracing::error!("Cannot parse chat completion chunk {:?}: {}", data, err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::{
+        backend::http::tests::{stub_server, stub_server_with_status},
+        models::{Conversation, GenerationSettings, Message},
+    };
+
+    #[rstest]
+    #[case(
+        r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","content":"The"}}]}"#,
+        Some(Ok("The"))
+    )]
+    #[case(
+        r#"data:{"choices":[{"index":0,"delta":{"content":" sky"},"finish_reason":null}]}"#,
+        Some(Ok(" sky"))
+    )]
+    #[case(
+        r#"data: {"error":{"code":500,"message":"context size exceeded","type":"server_error"}}"#,
+        Some(Err("server returned an error: context size exceeded"))
+    )]
+    #[case(
+        r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+        None
+    )]
+    #[case(r#"data: {"choices":[]}"#, None)]
+    #[case("data: [DONE]", None)]
+    #[case(": keep-alive", None)]
+    #[case("", None)]
+    #[case("data: not a json", None)]
+    fn test_parse_event(#[case] line: &str, #[case] expected: Option<Result<&str, &str>>) {
+        assert_eq!(
+            parse_event(line),
+            expected.map(|result| result
+                .map(|s| s.to_string())
+                .map_err(|err| StreamError(err.to_string())))
+        );
+    }
+
+    fn request() -> InferenceRequest {
+        InferenceRequest {
+            conversation: Conversation::new_test(1, "conversation 1"),
+            history: vec![Message::new_test(1, Role::User, "why is the sky blue?")],
+            rebuild_session: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream() -> AppResult<()> {
+        // given
+        let (url, request_body) = stub_server(
+            "text/event-stream",
+            concat!(
+                r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","content":"Because of "}}]}"#,
+                "\n\n",
+                r#"data: {"choices":[{"index":0,"delta":{"content":"Rayleigh scattering"}}]}"#,
+                "\n\n",
+                "data: [DONE]\n\n",
+                r#"data: {"choices":[{"index":0,"delta":{"content":"ignored"}}]}"#,
+                "\n\n",
+            ),
+        )
+        .await;
+        let backend = OpenAIBackend::new(url, "qwen2.5").with_api_key(Some("secret".to_string()));
+        let request = InferenceRequest {
+            conversation: Conversation {
+                settings: GenerationSettings {
+                    model: Some("qwen2.5-coder".to_string()),
+                    temperature: Some(0.5),
+                    max_tokens: Some(256),
+                    ..Default::default()
+                },
+                ..Conversation::new_test(1, "conversation 1")
+            },
+            history: vec![
+                Message::new_test(1, Role::User, "why is the sky blue?"),
+                Message::new_test(2, Role::Assistant, "I don't know"),
+                Message::new_test(3, Role::User, "think again"),
+            ],
+            rebuild_session: false,
+        };
+
+        // when
+        let response = backend
+            .stream(request)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // then
+        assert_eq!(response, vec!["Because of ", "Rayleigh scattering"]);
+
+        let request_body: serde_json::Value = serde_json::from_str(&request_body.await?)?;
+        assert_eq!(
+            request_body,
+            serde_json::json!({
+                "model": "qwen2.5-coder",
+                "messages": [
+                    {"role": "user", "content": "why is the sky blue?"},
+                    {"role": "assistant", "content": "I don't know"},
+                    {"role": "user", "content": "think again"},
+                ],
+                "stream": true,
+                "temperature": 0.5,
+                "max_tokens": 256,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_server_not_running() -> AppResult<()> {
+        // given
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        drop(listener);
+        let backend = OpenAIBackend::new(url.clone(), "qwen2.5");
+
+        // when
+        let result = backend.stream(request()).await;
+
+        // then
+        let err = result.err().map(|err| err.to_string()).unwrap_or_default();
+        assert!(err.starts_with(&format!("cannot connect to {url}")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_server_error() -> AppResult<()> {
+        // given
+        let (url, _request) = stub_server_with_status(
+            "500 Internal Server Error",
+            "text/plain",
+            "model is not loaded\n",
+        )
+        .await;
+        let backend = OpenAIBackend::new(url, "qwen2.5");
+
+        // when
+        let result = backend.stream(request()).await;
+
+        // then
+        assert_eq!(
+            result.err().map(|err| err.to_string()),
+            Some(
+                "server responded with 500 Internal Server Error: model is not loaded".to_string()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(Some("wrong"), Some("bearer wrong"))]
+    #[case(None, None)]
+    #[tokio::test]
+    async fn test_stream_unauthorized(
+        #[case] api_key: Option<&str>,
+        #[case] expected_authorization: Option<&str>,
+    ) -> AppResult<()> {
+        // given
+        let (url, received_request) = stub_server_with_status(
+            "401 Unauthorized",
+            "application/json",
+            r#"{"error":{"message":"Incorrect API key provided"}}"#,
+        )
+        .await;
+        let backend =
+            OpenAIBackend::new(url, "qwen2.5").with_api_key(api_key.map(|key| key.to_string()));
+
+        // when
+        let result = backend.stream(request()).await;
+
+        // then
+        assert_eq!(
+            result.err().map(|err| err.to_string()),
+            Some(
+                r#"server responded with 401 Unauthorized: {"error":{"message":"Incorrect API key provided"}}"#
+                    .to_string()
+            )
+        );
+        let (headers, _body) = received_request.await?;
+        let authorization = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("authorization:"))
+            .map(|value| value.trim());
+        assert_eq!(authorization, expected_authorization);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_model() {
+        let backend = OpenAIBackend::new(DEFAULT_BASE_URL, DEFAULT_MODEL);
+
+        assert!(backend.validate_model("llama3.2:1b").is_ok());
+        assert!(backend.validate_model("llama-3.2-1b-chat").is_err());
+        assert!(backend.validate_model("/models/model.gguf").is_err());
+    }
+}
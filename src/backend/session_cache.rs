@@ -0,0 +1,290 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use kalosm::language::Chat;
+use tokio::sync::Mutex;
+
+use crate::{models::GenerationSettings, AppResult};
+
+pub const DEFAULT_CAPACITY: usize = 4;
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Chat session that can be persisted to a session file.
+pub trait Session: Send + Sync + 'static {
+    fn save<'a>(&'a self, session_path: &'a str) -> BoxFuture<'a, AppResult<()>>;
+}
+
+impl Session for Chat {
+    fn save<'a>(&'a self, session_path: &'a str) -> BoxFuture<'a, AppResult<()>> {
+        async move {
+            self.save_session(session_path).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+pub struct CachedSession<S> {
+    pub session: S,
+    pub session_path: String,
+    /// Settings the session was built with, they cannot be changed without rebuilding it
+    pub settings: GenerationSettings,
+    /// Session has turns that aren't saved to the session file yet
+    pub dirty: bool,
+    last_used: Instant,
+    last_used_tick: u64,
+}
+
+impl<S: Session> CachedSession<S> {
+    pub fn new(session: S, session_path: impl Into<String>, settings: GenerationSettings) -> Self {
+        Self {
+            session,
+            session_path: session_path.into(),
+            settings,
+            dirty: false,
+            last_used: Instant::now(),
+            last_used_tick: 0,
+        }
+    }
+
+    /// Saves session to its file, errors are only logged as there is nobody to handle them.
+    pub async fn save(&mut self) {
+        match self.session.save(&self.session_path).await {
+            Ok(_) => {
+                tracing::info!("session saved to disk: {}", self.session_path);
+                self.dirty = false;
+            }
+            Err(err) => tracing::error!("Error while saving session: {}", err),
+        }
+    }
+}
+
+/// Bounded LRU cache of chat sessions keyed by conversation id. Sessions are written
+/// to their files when they get evicted, so active conversations don't hit the disk on every prompt.
+pub struct SessionCache<S> {
+    sessions: HashMap<u32, CachedSession<S>>,
+    /// Deleted conversations, their sessions are never saved again
+    forgotten: HashSet<u32>,
+    capacity: usize,
+    idle_timeout: Duration,
+    tick: u64,
+}
+
+impl<S: Session> SessionCache<S> {
+    pub fn new(capacity: usize, idle_timeout: Duration) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            forgotten: HashSet::new(),
+            capacity,
+            idle_timeout,
+            tick: 0,
+        }
+    }
+
+    /// Creates cache shared between backend and its generations, together with idle eviction task.
+    pub fn shared(capacity: usize, idle_timeout: Duration) -> Arc<Mutex<Self>> {
+        let sessions = Arc::new(Mutex::new(Self::new(capacity, idle_timeout)));
+        spawn_idle_eviction(Arc::downgrade(&sessions));
+
+        sessions
+    }
+
+    /// Removes session from the cache, it's given back with `insert` once generation is done.
+    pub fn take(&mut self, conversation_id: u32) -> Option<CachedSession<S>> {
+        self.sessions.remove(&conversation_id)
+    }
+
+    /// Puts session into the cache, the least recently used sessions are evicted when cache is full.
+    pub async fn insert(&mut self, conversation_id: u32, mut cached: CachedSession<S>) {
+        // conversation was deleted while its response was generated
+        if self.forgotten.contains(&conversation_id) {
+            return;
+        }
+
+        self.tick += 1;
+        cached.last_used = Instant::now();
+        cached.last_used_tick = self.tick;
+        self.sessions.insert(conversation_id, cached);
+
+        while self.sessions.len() > self.capacity {
+            let Some(least_recently_used) = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used_tick)
+                .map(|(conversation_id, _)| *conversation_id)
+            else {
+                break;
+            };
+            self.evict(least_recently_used).await;
+        }
+    }
+
+    /// Drops session of deleted conversation without saving it, so its file isn't written again.
+    pub fn forget(&mut self, conversation_id: u32) {
+        self.sessions.remove(&conversation_id);
+        self.forgotten.insert(conversation_id);
+    }
+
+    /// Evicts sessions that weren't used for longer than idle timeout.
+    pub async fn evict_idle(&mut self) {
+        let idle = self
+            .sessions
+            .iter()
+            .filter(|(_, cached)| cached.last_used.elapsed() >= self.idle_timeout)
+            .map(|(conversation_id, _)| *conversation_id)
+            .collect::<Vec<_>>();
+
+        for conversation_id in idle {
+            self.evict(conversation_id).await;
+        }
+    }
+
+    /// Saves all dirty sessions, they stay in the cache.
+    pub async fn flush(&mut self) {
+        for cached in self.sessions.values_mut() {
+            if cached.dirty {
+                cached.save().await;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    async fn evict(&mut self, conversation_id: u32) {
+        if let Some(mut cached) = self.sessions.remove(&conversation_id) {
+            if cached.dirty {
+                cached.save().await;
+            }
+        }
+    }
+}
+
+/// Periodically evicts idle sessions, task stops once the cache is dropped.
+fn spawn_idle_eviction<S: Session>(sessions: Weak<Mutex<SessionCache<S>>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(sessions) = sessions.upgrade() else {
+                break;
+            };
+            sessions.lock().await.evict_idle().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+
+    #[derive(Default, Clone)]
+    struct FakeSession {
+        saved_to: Arc<StdMutex<Vec<String>>>,
+    }
+
+    impl Session for FakeSession {
+        fn save<'a>(&'a self, session_path: &'a str) -> BoxFuture<'a, AppResult<()>> {
+            self.saved_to.lock().unwrap().push(session_path.to_string());
+            async { Ok(()) }.boxed()
+        }
+    }
+
+    fn cached(
+        session: &FakeSession,
+        session_path: &str,
+        dirty: bool,
+    ) -> CachedSession<FakeSession> {
+        let mut cached = CachedSession::new(session.clone(), session_path, Default::default());
+        cached.dirty = dirty;
+        cached
+    }
+
+    #[tokio::test]
+    async fn test_insert_evicts_least_recently_used() {
+        // given
+        let session = FakeSession::default();
+        let mut cache = SessionCache::new(2, Duration::from_secs(60));
+        cache.insert(1, cached(&session, "1.llama", true)).await;
+        cache.insert(2, cached(&session, "2.llama", true)).await;
+        let first = cache.take(1).unwrap();
+        cache.insert(1, first).await;
+
+        // when
+        cache.insert(3, cached(&session, "3.llama", true)).await;
+
+        // then
+        assert_eq!(cache.len(), 2);
+        assert!(cache.take(2).is_none());
+        assert!(cache.take(1).is_some());
+        assert_eq!(*session.saved_to.lock().unwrap(), vec!["2.llama"]);
+    }
+
+    #[tokio::test]
+    async fn test_forget() {
+        // given
+        let session = FakeSession::default();
+        let mut cache = SessionCache::new(2, Duration::ZERO);
+        cache.insert(1, cached(&session, "1.llama", true)).await;
+        cache.insert(2, cached(&session, "2.llama", true)).await;
+        let generating = cache.take(2).unwrap();
+
+        // when
+        cache.forget(1);
+        cache.forget(2);
+        cache.insert(2, generating).await;
+
+        // then
+        assert!(cache.is_empty());
+        cache.evict_idle().await;
+        cache.flush().await;
+        assert!(session.saved_to.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_evict_idle() {
+        // given
+        let session = FakeSession::default();
+        let mut cache = SessionCache::new(2, Duration::ZERO);
+        cache.insert(1, cached(&session, "1.llama", true)).await;
+        cache.insert(2, cached(&session, "2.llama", false)).await;
+
+        // when
+        cache.evict_idle().await;
+
+        // then
+        assert!(cache.is_empty());
+        assert_eq!(*session.saved_to.lock().unwrap(), vec!["1.llama"]);
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        // given
+        let session = FakeSession::default();
+        let mut cache = SessionCache::new(2, Duration::from_secs(60));
+        cache.insert(1, cached(&session, "1.llama", true)).await;
+        cache.insert(2, cached(&session, "2.llama", false)).await;
+
+        // when
+        cache.flush().await;
+        cache.flush().await;
+
+        // then
+        assert_eq!(cache.len(), 2);
+        assert_eq!(*session.saved_to.lock().unwrap(), vec!["1.llama"]);
+        assert!(!cache.take(1).unwrap().dirty);
+    }
+}
@@ -0,0 +1,479 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Row, SqlitePool,
+};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{db, doctor, AppResult};
+
+/// Version of the archive's layout, archives of other versions cannot be restored
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+const MANIFEST_ENTRY: &str = "manifest.json";
+const DATABASE_ENTRY: &str = "lokai.sqlite3";
+const CHATS_PREFIX: &str = "chats/";
+
+/// Describes content of a backup archive, it's used to verify the archive before it's restored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub lokai_version: String,
+    /// The last migration applied to the database
+    pub migration_version: i64,
+    pub created_at: DateTime<Utc>,
+    pub conversations: u32,
+    pub messages: u32,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl ManifestEntry {
+    fn new(name: String, data: &[u8]) -> Self {
+        Self {
+            name,
+            size: data.len() as u64,
+            sha256: sha256(data),
+        }
+    }
+
+    fn verify(&self, data: &[u8]) -> AppResult<()> {
+        if data.len() as u64 != self.size || sha256(data) != self.sha256 {
+            return Err(format!("Entry [{}] of backup archive is corrupted", self.name).into());
+        }
+
+        Ok(())
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Returns the latest migration known to this version of LokAI.
+fn latest_migration_version() -> i64 {
+    sqlx::migrate!()
+        .migrations
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default()
+}
+
+/// Archive is a SQLite Archive (https://sqlite.org/sqlar.html) that can be inspected
+/// with `sqlite3 <archive> -Atv`, entries are stored uncompressed.
+async fn open_archive(path: &Path, create: bool) -> AppResult<SqlitePool> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(create)
+        .read_only(!create);
+    let archive = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?;
+    if create {
+        sqlx::query(
+            r#"
+            CREATE TABLE sqlar(
+                name TEXT PRIMARY KEY,
+                mode INT,
+                mtime INT,
+                sz INT,
+                data BLOB
+            )
+            "#,
+        )
+        .execute(&archive)
+        .await?;
+    }
+
+    Ok(archive)
+}
+
+async fn write_entry(archive: &SqlitePool, name: &str, data: &[u8]) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO sqlar(name, mode, mtime, sz, data)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        "#,
+    )
+    .bind(name)
+    .bind(0o100644)
+    .bind(Utc::now().timestamp())
+    .bind(data.len() as i64)
+    .bind(data)
+    .execute(archive)
+    .await?;
+
+    Ok(())
+}
+
+async fn read_entry(archive: &SqlitePool, name: &str) -> AppResult<Vec<u8>> {
+    let row = sqlx::query("SELECT data FROM sqlar WHERE name = ?1")
+        .bind(name)
+        .fetch_optional(archive)
+        .await?
+        .ok_or_else(|| format!("Entry [{name}] is missing in backup archive"))?;
+
+    Ok(row.try_get("data")?)
+}
+
+async fn open_database(path: &Path, read_only: bool) -> AppResult<SqlitePool> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(read_only);
+    let sqlite = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?;
+
+    Ok(sqlite)
+}
+
+async fn count(sqlite: &SqlitePool, table: &str) -> AppResult<u32> {
+    let row = sqlx::query(&format!("SELECT COUNT(*) AS count FROM {table}"))
+        .fetch_one(sqlite)
+        .await?;
+
+    Ok(row.try_get("count")?)
+}
+
+/// Returns path of a database file, in-memory databases cannot be backed up nor restored.
+pub fn database_path(database_url: &str) -> AppResult<PathBuf> {
+    if database_url.contains(":memory:") || database_url.contains("mode=memory") {
+        return Err("In-memory database cannot be backed up nor restored".into());
+    }
+    let options = SqliteConnectOptions::from_str(database_url)?;
+
+    Ok(options.get_filename().to_path_buf())
+}
+
+/// Writes database snapshot and session files of all conversations into a single archive.
+/// `VACUUM INTO` makes a transactionally consistent copy while database stays online, only session
+/// files referenced by that copy are archived, so archive doesn't depend on changes made in the meantime.
+pub async fn backup(sqlite: &SqlitePool, archive_path: &Path) -> AppResult<Manifest> {
+    if archive_path.exists() {
+        return Err(format!("Backup archive {archive_path:?} already exists").into());
+    }
+    if let Some(dir) = archive_path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let snapshot_path =
+        std::env::temp_dir().join(format!("lokai-backup-{}.sqlite3", Uuid::new_v4()));
+    sqlx::query("VACUUM INTO ?1")
+        .bind(
+            snapshot_path
+                .to_str()
+                .expect("cannot convert path to string"),
+        )
+        .execute(sqlite)
+        .await?;
+    let result = write_archive(&snapshot_path, archive_path).await;
+    tokio::fs::remove_file(&snapshot_path).await?;
+    // incomplete archive could be mistaken for a valid one
+    if result.is_err() && archive_path.exists() {
+        tokio::fs::remove_file(archive_path).await?;
+    }
+
+    result
+}
+
+async fn write_archive(snapshot_path: &Path, archive_path: &Path) -> AppResult<Manifest> {
+    let snapshot = open_database(snapshot_path, true).await?;
+    let migration_version: Option<i64> =
+        sqlx::query("SELECT MAX(version) AS version FROM _sqlx_migrations WHERE success")
+            .fetch_one(&snapshot)
+            .await?
+            .try_get("version")?;
+    let conversations = db::get_all_conversations(&snapshot).await?;
+    let mut manifest = Manifest {
+        format_version: BACKUP_FORMAT_VERSION,
+        lokai_version: env!("CARGO_PKG_VERSION").to_string(),
+        migration_version: migration_version.unwrap_or_default(),
+        created_at: Utc::now(),
+        conversations: conversations.len() as u32,
+        messages: count(&snapshot, "messages").await?,
+        entries: vec![],
+    };
+    snapshot.close().await;
+
+    let archive = open_archive(archive_path, true).await?;
+    let data = tokio::fs::read(snapshot_path).await?;
+    write_entry(&archive, DATABASE_ENTRY, &data).await?;
+    manifest
+        .entries
+        .push(ManifestEntry::new(DATABASE_ENTRY.to_string(), &data));
+
+    for conversation in conversations {
+        let session_path = Path::new(&conversation.session_path);
+        // session file is created once the first response is generated, or it's rebuilt when missing
+        let (Some(file_name), true) = (session_path.file_name(), session_path.exists()) else {
+            continue;
+        };
+        let name = format!("{CHATS_PREFIX}{}", file_name.to_string_lossy());
+        let data = tokio::fs::read(session_path).await?;
+        write_entry(&archive, &name, &data).await?;
+        manifest.entries.push(ManifestEntry::new(name, &data));
+    }
+
+    write_entry(
+        &archive,
+        MANIFEST_ENTRY,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )
+    .await?;
+    archive.close().await;
+
+    Ok(manifest)
+}
+
+/// Verifies the archive and replaces database and session files with its content. Database must not
+/// be used while it's restored. Session paths are updated, so they point to given chats directory,
+/// session files that aren't part of the archive are moved aside.
+pub async fn restore(
+    archive_path: &Path,
+    database_path: &Path,
+    chats_dir: &Path,
+) -> AppResult<Manifest> {
+    let archive = open_archive(archive_path, false).await?;
+    let manifest: Manifest = serde_json::from_slice(&read_entry(&archive, MANIFEST_ENTRY).await?)?;
+    if manifest.format_version != BACKUP_FORMAT_VERSION {
+        return Err(format!(
+            "Unsupported backup format version [{}], expected [{}]",
+            manifest.format_version, BACKUP_FORMAT_VERSION
+        )
+        .into());
+    }
+    if manifest.migration_version > latest_migration_version() {
+        return Err(format!(
+            "Backup was made by newer LokAI [{}], its database cannot be restored",
+            manifest.lokai_version
+        )
+        .into());
+    }
+
+    // everything is staged next to its destination first, so nothing is replaced when verification fails
+    let staged_database = database_path.with_extension(format!("restore-{}", Uuid::new_v4()));
+    let staged_chats = chats_dir.join(format!(".restore-{}", Uuid::new_v4()));
+    let result = stage(
+        &archive,
+        &manifest,
+        &staged_database,
+        &staged_chats,
+        chats_dir,
+    )
+    .await;
+    archive.close().await;
+    if let Err(err) = result {
+        for path in [&staged_database, &staged_chats] {
+            if !path.exists() {
+                continue;
+            }
+            let removed = match path.is_dir() {
+                true => tokio::fs::remove_dir_all(path).await,
+                false => tokio::fs::remove_file(path).await,
+            };
+            if let Err(err) = removed {
+                warn!("cannot remove staged file {path:?}: {err}");
+            }
+        }
+        return Err(err);
+    }
+
+    tokio::fs::rename(&staged_database, database_path).await?;
+    // sessions of conversations missing in restored database would be left orphaned,
+    // they may still belong to another database, so they're only moved aside
+    let mut previous_sessions = vec![];
+    let mut files = tokio::fs::read_dir(chats_dir).await?;
+    while let Some(file) = files.next_entry().await? {
+        let path = file.path();
+        if file.file_type().await?.is_file()
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "llama")
+        {
+            previous_sessions.push(path);
+        }
+    }
+    doctor::quarantine(&previous_sessions, &doctor::quarantine_dir(chats_dir)).await?;
+    let mut files = tokio::fs::read_dir(&staged_chats).await?;
+    while let Some(file) = files.next_entry().await? {
+        tokio::fs::rename(file.path(), chats_dir.join(file.file_name())).await?;
+    }
+    tokio::fs::remove_dir(&staged_chats).await?;
+
+    Ok(manifest)
+}
+
+async fn stage(
+    archive: &SqlitePool,
+    manifest: &Manifest,
+    staged_database: &Path,
+    staged_chats: &Path,
+    chats_dir: &Path,
+) -> AppResult<()> {
+    tokio::fs::create_dir_all(staged_chats).await?;
+    let mut database_staged = false;
+    for entry in &manifest.entries {
+        let data = read_entry(archive, &entry.name).await?;
+        entry.verify(&data)?;
+        if entry.name == DATABASE_ENTRY {
+            tokio::fs::write(staged_database, data).await?;
+            database_staged = true;
+        } else if let Some(file_name) = entry
+            .name
+            .strip_prefix(CHATS_PREFIX)
+            .and_then(|name| Path::new(name).file_name())
+        {
+            tokio::fs::write(staged_chats.join(file_name), data).await?;
+        }
+    }
+    if !database_staged {
+        return Err(format!("Entry [{DATABASE_ENTRY}] is missing in backup archive").into());
+    }
+
+    let sqlite = open_database(staged_database, false).await?;
+    let integrity: String = sqlx::query("PRAGMA integrity_check")
+        .fetch_one(&sqlite)
+        .await?
+        .try_get(0)?;
+    if integrity != "ok" {
+        return Err(format!("Database of backup archive is corrupted: {integrity}").into());
+    }
+    let conversations = db::get_all_conversations(&sqlite).await?;
+    if conversations.len() as u32 != manifest.conversations
+        || count(&sqlite, "messages").await? != manifest.messages
+    {
+        return Err("Database of backup archive doesn't match its manifest".into());
+    }
+    for conversation in conversations {
+        if let Some(file_name) = Path::new(&conversation.session_path).file_name() {
+            let session_path = chats_dir.join(file_name);
+            db::update_session_path(
+                &sqlite,
+                conversation.id,
+                session_path
+                    .to_str()
+                    .expect("cannot convert path to string"),
+            )
+            .await?;
+        }
+    }
+    sqlite.close().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_backup_and_restore(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let dir = std::env::temp_dir().join(format!("lokai-backup-test-{}", Uuid::new_v4()));
+        let chats_dir = dir.join("chats");
+        tokio::fs::create_dir_all(&chats_dir).await?;
+        let session_path = chats_dir.join("1.llama");
+        tokio::fs::write(&session_path, b"session").await?;
+        db::update_session_path(&pool, 1, session_path.to_str().unwrap()).await?;
+        let archive_path = dir.join("backups").join("lokai.sqlar");
+
+        // when
+        let manifest = backup(&pool, &archive_path).await?;
+
+        // then
+        assert_eq!(manifest.conversations, 4);
+        assert_eq!(manifest.messages, 5);
+        assert_eq!(manifest.migration_version, latest_migration_version());
+        let names = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![DATABASE_ENTRY, "chats/1.llama"]);
+        assert!(backup(&pool, &archive_path).await.is_err());
+
+        let restored_chats_dir = dir.join("restored_chats");
+        tokio::fs::create_dir_all(&restored_chats_dir).await?;
+        let stale_session_path = restored_chats_dir.join("5.llama");
+        tokio::fs::write(&stale_session_path, b"session").await?;
+        let database_path = dir.join("restored.sqlite3");
+        assert_eq!(
+            restore(&archive_path, &database_path, &restored_chats_dir).await?,
+            manifest
+        );
+        let restored = open_database(&database_path, true).await?;
+        let conversation = db::get_conversation(&restored, 1).await?;
+        assert_eq!(
+            Path::new(&conversation.session_path),
+            restored_chats_dir.join("1.llama")
+        );
+        assert_eq!(
+            tokio::fs::read(&conversation.session_path).await?,
+            b"session"
+        );
+        assert_eq!(
+            db::get_messages(&restored, 2).await?,
+            db::get_messages(&pool, 2).await?
+        );
+        assert!(!stale_session_path.exists());
+        let quarantined = std::fs::read_dir(&restored_chats_dir)?
+            .filter_map(|entry| entry.ok())
+            .find(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(".orphaned-")
+            })
+            .map(|entry| entry.path().join("5.llama"));
+        assert!(quarantined.is_some_and(|path| path.exists()));
+        restored.close().await;
+
+        // corrupted archive isn't restored and nothing gets replaced
+        let archive = open_database(&archive_path, false).await?;
+        sqlx::query("UPDATE sqlar SET data = x'00' WHERE name = 'chats/1.llama'")
+            .execute(&archive)
+            .await?;
+        archive.close().await;
+        tokio::fs::remove_file(&database_path).await?;
+        assert!(restore(&archive_path, &database_path, &restored_chats_dir)
+            .await
+            .is_err());
+        assert!(!database_path.exists());
+        assert_eq!(
+            std::fs::read_dir(&restored_chats_dir)?
+                .filter(|entry| entry.as_ref().is_ok_and(|entry| entry.path().is_file()))
+                .count(),
+            1,
+            "only previously restored session file is left"
+        );
+
+        tokio::fs::remove_dir_all(dir).await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_database_path() {
+        assert_eq!(
+            database_path("sqlite://db.sqlite3").unwrap(),
+            PathBuf::from("db.sqlite3")
+        );
+        assert!(database_path("sqlite::memory:").is_err());
+    }
+}
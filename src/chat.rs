@@ -1,16 +1,56 @@
-use ratatui::widgets::{List, ListItem, Paragraph, ScrollbarState};
+use std::{
+    collections::HashMap,
+    ops::{Deref, Range},
+};
+
+use crossterm::event::KeyEvent;
+use ratatui::{
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{List, ListItem, Paragraph, ScrollbarState},
+};
 use sqlx::SqlitePool;
+use tui_textarea::{CursorMove, TextArea};
 
-use crate::{db, models::Message, AppResult};
+use crate::{
+    context::{self, ContextWindow},
+    db,
+    models::{Branch, Message, Role},
+    AppResult,
+};
 
 const BORDER_SIZE: usize = 1;
 
 // TODO: automatically scroll to the bottom when messages are loaded
 pub struct Chat {
     messages: Vec<Message>,
+    /// Positions of messages that have more than one version, keyed by message id
+    branches: HashMap<u32, Branch>,
     pub vertical_scrollbar_state: ScrollbarState,
     pub vertical_scroll: usize,
     vertical_scrollbar_content_length: usize,
+    /// Index of message selected for editing or switching branches
+    selected_message: Option<usize>,
+    context_window: ContextWindow,
+    /// Number of tokens available for the history of current conversation
+    context_budget: usize,
+    /// Summary of the oldest messages, it's shown after the last message it covers
+    summary: Option<Message>,
+    summary_expanded: bool,
+    /// Lines where rendered messages start, keyed by message id
+    message_offsets: HashMap<u32, usize>,
+    /// Message that chat gets scrolled to once it's rendered
+    scroll_to_message: Option<u32>,
+    /// Text searched within rendered messages, matches are highlighted
+    find_query: Option<String>,
+    /// Lines of rendered messages where matches start, one entry per match
+    find_matches: Vec<usize>,
+    /// Index of the match chat is scrolled to
+    find_match: Option<usize>,
+    /// Query changed since the last render, chat jumps to the closest match once it's rendered
+    find_pending: bool,
+    /// Errors of responses that failed since the application started, keyed by message id
+    errors: HashMap<u32, String>,
     sqlite: SqlitePool,
 }
 
@@ -18,18 +58,43 @@ impl Chat {
     pub fn new(sqlite: SqlitePool) -> Self {
         Self {
             messages: vec![],
+            branches: HashMap::new(),
             vertical_scrollbar_state: Default::default(),
             vertical_scroll: Default::default(),
             vertical_scrollbar_content_length: Default::default(),
+            selected_message: None,
+            context_window: Default::default(),
+            context_budget: usize::MAX,
+            summary: None,
+            summary_expanded: false,
+            message_offsets: HashMap::new(),
+            scroll_to_message: None,
+            find_query: None,
+            find_matches: vec![],
+            find_match: None,
+            find_pending: false,
+            errors: HashMap::new(),
             sqlite,
         }
     }
 
+    pub fn with_context_window(mut self, context_window: ContextWindow) -> Self {
+        self.context_window = context_window;
+        self
+    }
+
     pub fn reset(&mut self) {
         self.messages = vec![];
+        self.branches = HashMap::new();
         self.vertical_scroll = 0;
         self.vertical_scrollbar_content_length = 0;
         self.vertical_scrollbar_state.first();
+        self.selected_message = None;
+        self.context_budget = usize::MAX;
+        self.summary = None;
+        self.message_offsets = HashMap::new();
+        self.scroll_to_message = None;
+        self.find(None);
     }
 
     pub fn push_message(&mut self, message: Message) {
@@ -41,10 +106,78 @@ impl Chat {
         self.messages.pop();
     }
 
+    /// Remembers why response to given message failed, it's shown next to the message.
+    pub fn set_error(&mut self, message_id: u32, error: String) {
+        self.errors.insert(message_id, error);
+    }
+
+    pub fn errors(&self) -> &HashMap<u32, String> {
+        &self.errors
+    }
+
     pub fn get_last_message(&self) -> Option<&Message> {
         self.messages.last()
     }
 
+    pub fn selected_message(&self) -> Option<&Message> {
+        self.selected_message
+            .and_then(|index| self.messages.get(index))
+    }
+
+    pub fn get_message(&self, message_id: u32) -> Option<&Message> {
+        self.messages
+            .iter()
+            .find(|message| message.id == message_id)
+    }
+
+    pub fn select_message(&mut self, message_id: u32) {
+        self.selected_message = self
+            .messages
+            .iter()
+            .position(|message| message.id == message_id);
+    }
+
+    pub fn select_last_user_message(&mut self) {
+        self.selected_message = self
+            .messages
+            .iter()
+            .rposition(|message| message.role == Role::User);
+    }
+
+    /// Selects message before currently selected one, starts from the last message.
+    /// System prompt cannot be selected.
+    pub fn select_previous_message(&mut self) {
+        let end = self.selected_message.unwrap_or(self.messages.len());
+        if let Some(index) = self.messages[..end]
+            .iter()
+            .rposition(|message| message.role != Role::System)
+        {
+            self.selected_message = Some(index);
+        }
+    }
+
+    /// Selects message after currently selected one, does nothing when nothing is selected.
+    pub fn select_next_message(&mut self) {
+        let Some(start) = self.selected_message.map(|index| index + 1) else {
+            return;
+        };
+        if start < self.messages.len() {
+            self.selected_message = Some(start);
+        }
+    }
+
+    pub fn unselect_message(&mut self) {
+        self.selected_message = None;
+    }
+
+    pub fn summary_expanded(&self) -> bool {
+        self.summary_expanded
+    }
+
+    pub fn toggle_summary(&mut self) {
+        self.summary_expanded = !self.summary_expanded;
+    }
+
     pub fn scroll_up(&mut self) {
         self.vertical_scroll = self.vertical_scroll.saturating_sub(1);
         self.vertical_scrollbar_state =
@@ -64,6 +197,64 @@ impl Chat {
             self.vertical_scrollbar_state.position(self.vertical_scroll);
     }
 
+    /// Scrolls chat to the first line of given message, lines are known once chat is rendered.
+    pub fn scroll_to_message(&mut self, message_id: u32) {
+        self.scroll_to_message = Some(message_id);
+    }
+
+    fn scroll_to_line(&mut self, line: usize) {
+        self.vertical_scroll = line.min(self.vertical_scrollbar_content_length);
+        self.vertical_scrollbar_state =
+            self.vertical_scrollbar_state.position(self.vertical_scroll);
+    }
+
+    /// Highlights occurrences of the query in rendered messages, empty query stops searching.
+    pub fn find(&mut self, query: Option<String>) {
+        self.find_query = query.filter(|query| !query.is_empty());
+        self.find_matches = vec![];
+        self.find_match = None;
+        self.find_pending = self.find_query.is_some();
+    }
+
+    pub fn is_finding(&self) -> bool {
+        self.find_query.is_some()
+    }
+
+    /// Position of the current match and number of all matches.
+    pub fn find_status(&self) -> Option<(usize, usize)> {
+        self.find_query.as_ref()?;
+        Some((
+            self.find_match.map_or(0, |index| index + 1),
+            self.find_matches.len(),
+        ))
+    }
+
+    /// Scrolls chat to the match after the current one, wraps around at the end.
+    pub fn next_match(&mut self) {
+        if self.find_matches.is_empty() {
+            return;
+        }
+        let index = match self.find_match {
+            Some(index) => (index + 1) % self.find_matches.len(),
+            None => 0,
+        };
+        self.find_match = Some(index);
+        self.scroll_to_line(self.find_matches[index]);
+    }
+
+    /// Scrolls chat to the match before the current one, wraps around at the beginning.
+    pub fn previous_match(&mut self) {
+        if self.find_matches.is_empty() {
+            return;
+        }
+        let index = match self.find_match {
+            Some(index) => index.checked_sub(1).unwrap_or(self.find_matches.len() - 1),
+            None => self.find_matches.len() - 1,
+        };
+        self.find_match = Some(index);
+        self.scroll_to_line(self.find_matches[index]);
+    }
+
     pub fn scroll_to_bottom(&mut self) {
         while self.vertical_scroll != self.vertical_scrollbar_content_length {
             self.scroll_down();
@@ -73,8 +264,30 @@ impl Chat {
     pub async fn load_messages(&mut self, conversation_id: u32) -> AppResult<()> {
         self.reset();
 
+        let conversation = db::get_conversation(&self.sqlite, conversation_id).await?;
+        self.context_budget = self.context_window.budget(&conversation.settings);
         let messages = db::get_messages(&self.sqlite, conversation_id).await?;
         self.messages = messages;
+        self.load_branches(conversation_id).await?;
+        self.load_summary(conversation_id).await?;
+
+        Ok(())
+    }
+
+    pub async fn load_summary(&mut self, conversation_id: u32) -> AppResult<()> {
+        let summaries = db::get_summaries(&self.sqlite, conversation_id).await?;
+        self.summary =
+            context::latest_summary(&summaries, &self.messages).map(|(_, summary)| summary.clone());
+
+        Ok(())
+    }
+
+    pub async fn load_branches(&mut self, conversation_id: u32) -> AppResult<()> {
+        self.branches = db::get_branches(&self.sqlite, conversation_id)
+            .await?
+            .into_iter()
+            .map(|branch| (branch.message_id, branch))
+            .collect();
 
         Ok(())
     }
@@ -96,19 +309,250 @@ impl Chat {
 
     pub fn as_paragraph<F>(&mut self, f: F, area_height: usize) -> Paragraph<'static>
     where
-        F: Fn(&Message) -> String,
+        F: Fn(&Message, Option<&Branch>, bool) -> String,
     {
-        let text = self.messages.iter().map(f).collect::<Vec<_>>().join("\n");
+        let out_of_context = self
+            .context_window
+            .out_of_context(&self.messages, self.context_budget);
+        let summary_parent_id = self.summary.as_ref().and_then(|summary| summary.parent_id);
+        let texts = self
+            .messages
+            .iter()
+            .flat_map(|message| {
+                let summary = self
+                    .summary
+                    .as_ref()
+                    .filter(|_| summary_parent_id == Some(message.id))
+                    .map(|summary| (summary.id, f(summary, None, true)));
+                let text = f(
+                    message,
+                    self.branches.get(&message.id),
+                    !out_of_context.contains(&message.id),
+                );
+
+                std::iter::once((message.id, text)).chain(summary)
+            })
+            .collect::<Vec<_>>();
+
+        self.message_offsets.clear();
+        let mut offset = 0;
+        for (message_id, text) in &texts {
+            self.message_offsets.insert(*message_id, offset);
+            offset += text.split('\n').count();
+        }
+        let text = texts
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect::<Vec<_>>()
+            .join("\n");
 
         self.vertical_scrollbar_content_length =
             calculate_vertical_scrollbar_content_length(&text, area_height);
 
+        if let Some(offset) = self
+            .scroll_to_message
+            .take()
+            .and_then(|message_id| self.message_offsets.get(&message_id).copied())
+        {
+            self.scroll_to_line(offset);
+        }
         self.vertical_scrollbar_state = self
             .vertical_scrollbar_state
             .content_length(self.vertical_scrollbar_content_length);
 
-        Paragraph::new(text)
+        let Some(query) = self.find_query.clone() else {
+            return Paragraph::new(text);
+        };
+
+        let lines = text
+            .split('\n')
+            .map(|line| find_matches(line, &query))
+            .collect::<Vec<_>>();
+        self.find_matches = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(index, matches)| std::iter::repeat(index).take(matches.len()))
+            .collect();
+        if self.find_pending {
+            self.find_pending = false;
+            // the closest match below the top of the chat, so typing doesn't move chat back and forth
+            let index = self
+                .find_matches
+                .iter()
+                .position(|line| *line >= self.vertical_scroll)
+                .or((!self.find_matches.is_empty()).then_some(0));
+            self.find_match = index;
+            if let Some(index) = index {
+                self.scroll_to_line(self.find_matches[index]);
+            }
+        }
+
+        let mut match_index = 0;
+        let text = text
+            .split('\n')
+            .zip(lines)
+            .map(|(line, matches)| {
+                let mut spans = vec![];
+                let mut end = 0;
+                for range in matches {
+                    spans.push(Span::raw(line[end..range.start].to_string()));
+                    let style = match self.find_match == Some(match_index) {
+                        true => Style::default().black().on_yellow(),
+                        false => Style::default().reversed(),
+                    };
+                    spans.push(Span::styled(line[range.clone()].to_string(), style));
+                    end = range.end;
+                    match_index += 1;
+                }
+                spans.push(Span::raw(line[end..].to_string()));
+
+                Line::from(spans)
+            })
+            .collect::<Vec<_>>();
+
+        Paragraph::new(Text::from(text))
+    }
+}
+
+#[derive(Default)]
+pub struct EditMessagePopup {
+    message: Option<Message>,
+    text_area: TextArea<'static>,
+}
+
+impl Deref for EditMessagePopup {
+    type Target = TextArea<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.text_area
+    }
+}
+
+impl EditMessagePopup {
+    pub fn is_activated(&self) -> bool {
+        self.message.is_some()
+    }
+
+    pub fn activate(&mut self, message: &Message) {
+        self.text_area = TextArea::new(message.content.lines().map(String::from).collect());
+        self.text_area.move_cursor(CursorMove::Bottom);
+        self.text_area.move_cursor(CursorMove::End);
+        self.message = Some(message.clone());
+    }
+
+    pub fn deactivate(&mut self) {
+        self.message = None;
+        self.text_area = Default::default();
+    }
+
+    pub fn handle_input(&mut self, key_event: KeyEvent) {
+        if !self.is_activated() {
+            panic!("Activate popup before you handle input")
+        }
+
+        self.text_area.input(key_event);
+    }
+
+    pub fn text_area_mut(&mut self) -> &mut TextArea<'static> {
+        &mut self.text_area
+    }
+
+    /// Message that is being edited.
+    pub fn message(&self) -> Option<&Message> {
+        self.message.as_ref()
+    }
+
+    /// Returns edited content, unless it's empty or unchanged.
+    pub fn get_content(&self) -> Option<String> {
+        let content = self.text_area.lines().join("\n").trim().to_string();
+        let original = self.message.as_ref()?;
+        (!content.is_empty() && content != original.content).then_some(content)
+    }
+}
+
+/// Input of the text searched within the current conversation.
+#[derive(Default)]
+pub struct FindBar {
+    text_area: TextArea<'static>,
+    activated: bool,
+}
+
+impl Deref for FindBar {
+    type Target = TextArea<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.text_area
+    }
+}
+
+impl FindBar {
+    pub fn is_activated(&self) -> bool {
+        self.activated
+    }
+
+    pub fn activate(&mut self) {
+        self.activated = true;
     }
+
+    /// Hides the input, query is kept so matches can still be highlighted.
+    pub fn deactivate(&mut self) {
+        self.activated = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.text_area = Default::default();
+    }
+
+    pub fn handle_input(&mut self, key_event: KeyEvent) {
+        if !self.activated {
+            panic!("Activate popup before you handle input")
+        }
+
+        self.text_area.input(key_event);
+    }
+
+    pub fn text_area_mut(&mut self) -> &mut TextArea<'static> {
+        &mut self.text_area
+    }
+
+    pub fn get_query(&self) -> Option<String> {
+        let query = self.text_area.lines().join(" ");
+        (!query.trim().is_empty()).then_some(query)
+    }
+}
+
+/// Finds non-overlapping, case insensitive occurrences of the query in a line of text.
+fn find_matches(line: &str, query: &str) -> Vec<Range<usize>> {
+    let query = query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        return vec![];
+    }
+
+    let mut matches = vec![];
+    let mut start = 0;
+    while let Some(first) = line[start..].chars().next() {
+        let mut lowercase = vec![];
+        let mut end = start;
+        for c in line[start..].chars() {
+            if lowercase.len() >= query.len() {
+                break;
+            }
+            lowercase.extend(c.to_lowercase());
+            end += c.len_utf8();
+        }
+
+        if lowercase == query {
+            matches.push(start..end);
+            start = end;
+        } else {
+            start += first.len_utf8();
+        }
+    }
+
+    matches
 }
 
 fn calculate_vertical_scrollbar_content_length(text: &str, area_height: usize) -> usize {
@@ -127,8 +571,201 @@ fn calculate_vertical_scrollbar_content_length(text: &str, area_height: usize) -
 mod tests {
     use super::*;
 
+    use crossterm::event::{KeyCode, KeyModifiers};
     use rstest::rstest;
 
+    #[sqlx::test]
+    async fn test_select_messages(pool: SqlitePool) {
+        // given
+        let mut chat = Chat::new(pool);
+        chat.push_message(Message::new_test(1, Role::System, "Be fun"));
+        chat.push_message(Message::new_test(2, Role::User, "why is the sky blue?"));
+        chat.push_message(Message::new_test(3, Role::Assistant, "I don't know"));
+        chat.push_message(Message::new_test(4, Role::User, "tell me a joke"));
+        chat.push_message(Message::new_test(
+            5,
+            Role::Assistant,
+            "I don't know any jokes",
+        ));
+
+        // when
+        chat.select_next_message();
+
+        // then
+        assert_eq!(chat.selected_message(), None);
+
+        chat.select_previous_message();
+        assert_eq!(chat.selected_message().map(|message| message.id), Some(5));
+        chat.select_previous_message();
+        chat.select_previous_message();
+        chat.select_previous_message();
+        assert_eq!(chat.selected_message().map(|message| message.id), Some(2));
+        chat.select_previous_message();
+        assert_eq!(chat.selected_message().map(|message| message.id), Some(2));
+        chat.select_next_message();
+        assert_eq!(chat.selected_message().map(|message| message.id), Some(3));
+        chat.select_last_user_message();
+        assert_eq!(chat.selected_message().map(|message| message.id), Some(4));
+        chat.select_message(5);
+        chat.select_next_message();
+        assert_eq!(chat.selected_message().map(|message| message.id), Some(5));
+
+        chat.reset();
+        assert_eq!(chat.selected_message(), None);
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_load_messages_with_branches(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let new_response =
+            db::create_child_message(&pool, Role::Assistant, "Rayleigh scattering", 1, Some(1))
+                .await?;
+        let mut chat = Chat::new(pool);
+
+        // when
+        chat.load_messages(1).await?;
+
+        // then
+        assert_eq!(chat.get_last_message(), Some(&new_response));
+        assert_eq!(chat.get_message(1).map(|message| message.id), Some(1));
+        let branches = chat
+            .messages
+            .iter()
+            .map(|message| chat.branches.get(&message.id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            branches,
+            vec![
+                None,
+                Some(&Branch {
+                    message_id: new_response.id,
+                    position: 2,
+                    count: 2
+                })
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_message_popup() {
+        // given
+        let mut popup = EditMessagePopup::default();
+        let message = Message::new_test(1, Role::User, "why is the sky blu");
+        assert!(!popup.is_activated());
+
+        // when
+        popup.activate(&message);
+
+        // then
+        assert!(popup.is_activated());
+        assert_eq!(popup.get_content(), None);
+
+        popup.handle_input(KeyEvent::new(KeyCode::Char('e'), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('?'), KeyModifiers::NONE));
+        assert_eq!(
+            popup.get_content(),
+            Some("why is the sky blue?".to_string())
+        );
+        assert_eq!(popup.message(), Some(&message));
+
+        popup.deactivate();
+        assert!(!popup.is_activated());
+        assert_eq!(popup.get_content(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_edit_message_popup_handle_input_not_activated() {
+        // given
+        let mut popup = EditMessagePopup::default();
+
+        // when
+        popup.handle_input(KeyEvent::new(KeyCode::Char('H'), KeyModifiers::SHIFT));
+    }
+
+    #[sqlx::test]
+    async fn test_find(pool: SqlitePool) {
+        // given
+        let mut chat = Chat::new(pool);
+        chat.push_message(Message::new_test(1, Role::User, "why is the sky blue?"));
+        chat.push_message(Message::new_test(
+            2,
+            Role::Assistant,
+            "I don't know\n\n\nBlue\nsky",
+        ));
+        chat.push_message(Message::new_test(3, Role::User, "the Sky is BLUE!"));
+        let render = |chat: &mut Chat| {
+            chat.as_paragraph(|message, _, _| message.content.clone(), 5);
+        };
+
+        // when
+        chat.find(Some("blue".to_string()));
+        render(&mut chat);
+
+        // then
+        assert!(chat.is_finding());
+        assert_eq!(chat.find_status(), Some((1, 3)));
+        assert_eq!(chat.vertical_scroll, 0);
+
+        chat.next_match();
+        assert_eq!(chat.find_status(), Some((2, 3)));
+        assert_eq!(chat.vertical_scroll, 4);
+        chat.next_match();
+        assert_eq!(chat.find_status(), Some((3, 3)));
+        assert_eq!(chat.vertical_scroll, 6);
+        chat.next_match();
+        assert_eq!(chat.find_status(), Some((1, 3)));
+        assert_eq!(chat.vertical_scroll, 0);
+        chat.previous_match();
+        assert_eq!(chat.find_status(), Some((3, 3)));
+
+        chat.find(Some("nothing".to_string()));
+        render(&mut chat);
+        chat.next_match();
+        assert_eq!(chat.find_status(), Some((0, 0)));
+
+        chat.find(None);
+        assert!(!chat.is_finding());
+        assert_eq!(chat.find_status(), None);
+    }
+
+    #[test]
+    fn test_find_bar() {
+        // given
+        let mut find_bar = FindBar::default();
+        find_bar.activate();
+
+        // when
+        find_bar.handle_input(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::NONE));
+        find_bar.handle_input(KeyEvent::new(KeyCode::Char('k'), KeyModifiers::NONE));
+        find_bar.handle_input(KeyEvent::new(KeyCode::Char('y'), KeyModifiers::NONE));
+        find_bar.deactivate();
+
+        // then
+        assert!(!find_bar.is_activated());
+        assert_eq!(find_bar.get_query(), Some("sky".to_string()));
+
+        find_bar.clear();
+        assert_eq!(find_bar.get_query(), None);
+    }
+
+    #[rstest]
+    #[case("why is the sky blue?", "sky", vec![11..14])]
+    #[case("Sky is BLUE, sky is blue", "sky", vec![0..3, 13..16])]
+    #[case("aaaa", "aa", vec![0..2, 2..4])]
+    #[case("Zażółć gęślą jaźń", "ŻÓŁ", vec![2..8])]
+    #[case("why is the sky blue?", "", vec![])]
+    #[case("", "sky", vec![])]
+    fn test_find_matches(
+        #[case] line: &str,
+        #[case] query: &str,
+        #[case] expected: Vec<Range<usize>>,
+    ) {
+        assert_eq!(find_matches(line, query), expected);
+    }
+
     #[rstest]
     #[case("Line 1\nLine 2\nLine 3", 10, 0)]
     #[case("Line 1\nLine 2\nLine 3\nLine 4\nLine 5", 7, 0)]
@@ -0,0 +1,300 @@
+use std::{path::PathBuf, sync::Arc};
+
+use clap::Subcommand;
+use kalosm_language::kalosm_llama::Cache;
+use sqlx::SqlitePool;
+
+use crate::{
+    backend::{BackendKind, InferenceBackend},
+    backup, db, doctor,
+    embeddings::{self, BertEmbedder},
+    export::{self, ExportFormat},
+    import::{self, ImportFormat},
+    setup_inference_backend, AppResult, CONFIG,
+};
+
+/// Commands that are run instead of the TUI.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Rebuilds session files of all conversations from messages stored in the database
+    RebuildSessions,
+    /// Reports session files without conversations and database rows that lost their references
+    #[command(alias = "gc")]
+    Doctor {
+        /// Moves orphaned session files aside, removes dangling rows, rebuilds missing sessions
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        fix: bool,
+    },
+    /// Embeds messages that weren't embedded yet, so related conversations can be found
+    EmbedMessages,
+    /// Exports conversations into files
+    Export {
+        /// Conversation that is exported, all conversations are exported when not set
+        #[arg(long)]
+        conversation_id: Option<u32>,
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// Directory files are written to [default: ~/.lokai/exports]
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+    },
+    /// Writes database and session files into a single archive
+    Backup {
+        /// Path of the archive [default: ~/.lokai/backups/lokai-<timestamp>.sqlar]
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Verifies the archive and replaces database and session files with its content
+    Restore {
+        /// Archive created by backup command
+        archive: PathBuf,
+        /// Replaces database that already has conversations
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+    },
+    /// Imports conversations exported from ChatGPT, Open WebUI or LokAI
+    Import {
+        /// JSON file with exported conversations
+        path: PathBuf,
+        /// Format of the file, it's detected when not set
+        #[arg(long, value_enum)]
+        format: Option<ImportFormat>,
+        /// Reports what would be imported without storing anything
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
+    },
+}
+
+impl Command {
+    pub async fn run(self, sqlite: SqlitePool, kalosm_cache: Cache) -> AppResult<()> {
+        match self {
+            Command::RebuildSessions => rebuild_sessions(sqlite, kalosm_cache).await,
+            Command::Doctor { fix } => doctor(sqlite, kalosm_cache, fix).await,
+            Command::EmbedMessages => embed_messages(sqlite, kalosm_cache).await,
+            Command::Export {
+                conversation_id,
+                format,
+                output_dir,
+            } => export_conversations(sqlite, conversation_id, format, output_dir).await,
+            Command::Backup { output } => backup(sqlite, output).await,
+            Command::Restore { archive, force } => restore(sqlite, archive, force).await,
+            Command::Import {
+                path,
+                format,
+                dry_run,
+            } => import_conversations(sqlite, path, format, dry_run).await,
+        }
+    }
+}
+
+/// Builds configured inference backend, only commands that write session files need it,
+/// so the others don't load the model.
+async fn inference_backend(kalosm_cache: Cache) -> AppResult<Arc<dyn InferenceBackend>> {
+    let inference_config = CONFIG.read().await.inference().clone();
+    setup_inference_backend(inference_config, kalosm_cache).await
+}
+
+async fn rebuild_sessions(sqlite: SqlitePool, kalosm_cache: Cache) -> AppResult<()> {
+    // only llama backend keeps conversations in session files
+    let backend = CONFIG.read().await.inference().backend;
+    if let Some(backend) = backend.filter(|backend| *backend != BackendKind::Llama) {
+        return Err(format!(
+            "{} backend doesn't keep sessions, only llama backend's sessions can be rebuilt",
+            format!("{backend:?}").to_lowercase()
+        )
+        .into());
+    }
+
+    let inference_backend = inference_backend(kalosm_cache).await?;
+    // sessions of conversations in trash are needed once they're restored
+    let conversations = db::get_all_conversations(&sqlite).await?;
+    for conversation in conversations {
+        let history = db::get_messages(&sqlite, conversation.id).await?;
+        let name = conversation.name.clone();
+        inference_backend
+            .rebuild_session(conversation, history)
+            .await?;
+        println!("rebuilt session of conversation: {}", name);
+    }
+    inference_backend.flush().await?;
+
+    Ok(())
+}
+
+async fn doctor(sqlite: SqlitePool, kalosm_cache: Cache, fix: bool) -> AppResult<()> {
+    let (chats_dir, backend) = {
+        let config = CONFIG.read().await;
+        (config.chats_dir(), config.inference().backend)
+    };
+    let mut report = doctor::check(&sqlite, &chats_dir).await?;
+    // only llama backend keeps conversations in session files
+    if backend.unwrap_or(BackendKind::Llama) != BackendKind::Llama {
+        report.missing_sessions.clear();
+    }
+    for path in &report.orphaned_files {
+        println!("orphaned session file: {}", path.display());
+    }
+    for message in &report.dangling_messages {
+        println!(
+            "message {} of deleted conversation: {}",
+            message.id, message.conversation_id
+        );
+    }
+    for conversation in &report.broken_branches {
+        println!(
+            "broken active branch of conversation: {}",
+            conversation.name
+        );
+    }
+    for conversation in &report.missing_sessions {
+        println!(
+            "missing session file of conversation: {}",
+            conversation.name
+        );
+    }
+    if report.problems() == 0 && report.missing_sessions.is_empty() {
+        println!("no problems found");
+        return Ok(());
+    }
+
+    if fix {
+        let inference_backend = inference_backend(kalosm_cache).await?;
+        let quarantine_dir =
+            doctor::fix(&sqlite, &report, &chats_dir, inference_backend.as_ref()).await?;
+        inference_backend.flush().await?;
+        if let Some(dir) = quarantine_dir {
+            println!(
+                "moved {} session files to {}, remove them once they aren't needed",
+                report.orphaned_files.len(),
+                dir.display()
+            );
+        }
+        println!(
+            "removed {} messages, repaired {} branches, rebuilt {} sessions",
+            report.dangling_messages.len(),
+            report.broken_branches.len(),
+            report.missing_sessions.len()
+        );
+    } else {
+        println!("run with --fix to clean them up, missing sessions are also rebuilt when conversation is continued");
+    }
+
+    Ok(())
+}
+
+async fn embed_messages(sqlite: SqlitePool, kalosm_cache: Cache) -> AppResult<()> {
+    let embedder = BertEmbedder::new(kalosm_cache).await?;
+    let count = embeddings::embed_messages(&sqlite, &embedder).await?;
+    println!("embedded messages: {}", count);
+
+    Ok(())
+}
+
+async fn export_conversations(
+    sqlite: SqlitePool,
+    conversation_id: Option<u32>,
+    format: ExportFormat,
+    output_dir: Option<PathBuf>,
+) -> AppResult<()> {
+    let output_dir = match output_dir {
+        Some(output_dir) => output_dir,
+        None => CONFIG.read().await.exports_dir(),
+    };
+    let conversation_ids = match conversation_id {
+        Some(conversation_id) => vec![conversation_id],
+        None => db::get_conversations(&sqlite)
+            .await?
+            .into_iter()
+            .map(|conversation| conversation.id)
+            .collect(),
+    };
+    for conversation_id in conversation_ids {
+        let path =
+            export::export_conversation(&sqlite, conversation_id, format, &output_dir).await?;
+        println!("exported conversation: {}", path.display());
+    }
+
+    Ok(())
+}
+
+async fn import_conversations(
+    sqlite: SqlitePool,
+    path: PathBuf,
+    format: Option<ImportFormat>,
+    dry_run: bool,
+) -> AppResult<()> {
+    let content = tokio::fs::read_to_string(&path).await?;
+    let conversations = import::parse(&content, format)?;
+    let config = CONFIG.read().await;
+    // session files don't exist yet, they're rebuilt from messages when conversation is continued
+    let new_session_path = || {
+        config
+            .random_session_path()
+            .to_str()
+            .expect("cannot convert to path to string")
+            .to_string()
+    };
+    let report =
+        import::import_conversations(&sqlite, conversations, new_session_path, dry_run).await?;
+
+    let action = match dry_run {
+        true => "would import",
+        false => "imported",
+    };
+    for (name, messages) in &report.imported {
+        println!("{action} conversation: {name} ({messages} messages)");
+    }
+    for name in &report.skipped {
+        println!("skipped already imported conversation: {name}");
+    }
+    println!(
+        "{action} {} conversations, skipped {}",
+        report.imported.len(),
+        report.skipped.len()
+    );
+
+    Ok(())
+}
+
+async fn backup(sqlite: SqlitePool, output: Option<PathBuf>) -> AppResult<()> {
+    let archive_path = match output {
+        Some(output) => output,
+        None => CONFIG.read().await.backups_dir().join(format!(
+            "lokai-{}.sqlar",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        )),
+    };
+    let manifest = backup::backup(&sqlite, &archive_path).await?;
+    println!(
+        "backed up {} conversations, {} messages and {} session files into: {}",
+        manifest.conversations,
+        manifest.messages,
+        manifest.entries.len() - 1,
+        archive_path.display()
+    );
+
+    Ok(())
+}
+
+async fn restore(sqlite: SqlitePool, archive: PathBuf, force: bool) -> AppResult<()> {
+    let (database_path, chats_dir) = {
+        let config = CONFIG.read().await;
+        (
+            backup::database_path(config.database_url())?,
+            config.chats_dir(),
+        )
+    };
+    if !force && !db::get_all_conversations(&sqlite).await?.is_empty() {
+        return Err("Database already has conversations, use --force to replace them".into());
+    }
+    // database file is replaced, so no connection can be left open
+    sqlite.close().await;
+
+    let manifest = backup::restore(&archive, &database_path, &chats_dir).await?;
+    println!(
+        "restored {} conversations and {} messages backed up by LokAI {} at {}",
+        manifest.conversations, manifest.messages, manifest.lokai_version, manifest.created_at
+    );
+
+    Ok(())
+}
@@ -1,11 +1,87 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use clap::Args;
+use serde::Deserialize;
 use uuid::Uuid;
 
+use crate::{
+    backend::BackendKind,
+    context::{self, ContextPolicy, ContextWindow},
+    AppResult,
+};
+
+/// Inference options, they can be set in `config.toml` file and overridden from CLI.
+#[derive(Args, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct InferenceConfig {
+    /// Inference backend [default: llama]
+    #[arg(long, value_enum)]
+    pub backend: Option<BackendKind>,
+    /// Model used by the backend, for llama it's either one of built-in models or a path to GGUF file
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Tokenizer used together with local GGUF model
+    #[arg(long)]
+    pub tokenizer: Option<PathBuf>,
+    /// Base URL of a server used by HTTP backends
+    #[arg(long)]
+    pub base_url: Option<String>,
+    /// API key sent as a bearer token by openai backend
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// JSON file with a list of replies used by mock backend, prompts are echoed when not set
+    #[arg(long)]
+    pub mock_replies: Option<PathBuf>,
+    /// Delay between tokens streamed by mock backend in milliseconds [default: 30]
+    #[arg(long)]
+    pub mock_token_delay: Option<u64>,
+    /// Number of llama chat sessions kept in memory [default: 4]
+    #[arg(long)]
+    pub session_cache_size: Option<usize>,
+    /// Seconds after which idle llama chat session is saved to disk and dropped from memory [default: 600]
+    #[arg(long)]
+    pub session_idle_timeout: Option<u64>,
+    /// Number of tokens model can attend to, older messages are truncated once conversation exceeds it [default: 4096]
+    #[arg(long)]
+    pub context_size: Option<usize>,
+    /// What happens with the oldest messages once conversation exceeds context size [default: keep-system-prompt]
+    #[arg(long, value_enum)]
+    pub context_policy: Option<ContextPolicy>,
+}
+
+impl InferenceConfig {
+    /// Overrides options with the ones that are set in `other`.
+    pub fn merge(&mut self, other: InferenceConfig) {
+        self.backend = other.backend.or(self.backend);
+        self.model = other.model.or(self.model.take());
+        self.tokenizer = other.tokenizer.or(self.tokenizer.take());
+        self.base_url = other.base_url.or(self.base_url.take());
+        self.api_key = other.api_key.or(self.api_key.take());
+        self.mock_replies = other.mock_replies.or(self.mock_replies.take());
+        self.mock_token_delay = other.mock_token_delay.or(self.mock_token_delay);
+        self.session_cache_size = other.session_cache_size.or(self.session_cache_size);
+        self.session_idle_timeout = other.session_idle_timeout.or(self.session_idle_timeout);
+        self.context_size = other.context_size.or(self.context_size);
+        self.context_policy = other.context_policy.or(self.context_policy);
+    }
+
+    pub fn context_window(&self) -> ContextWindow {
+        ContextWindow {
+            size: self.context_size.unwrap_or(context::DEFAULT_CONTEXT_SIZE),
+            policy: self.context_policy.unwrap_or_default(),
+        }
+    }
+}
+
+/// Days after which conversations in trash are permanently deleted
+pub const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     lokai_dir: PathBuf,
     database_url: String,
+    inference: InferenceConfig,
+    trash_retention_days: u32,
 }
 
 impl Default for Config {
@@ -32,6 +108,8 @@ impl Config {
         let config = Config {
             lokai_dir,
             database_url,
+            inference: Default::default(),
+            trash_retention_days: DEFAULT_TRASH_RETENTION_DAYS,
         };
 
         create_dir_if_not_exists(&config.logs_dir());
@@ -45,6 +123,18 @@ impl Config {
         &self.database_url
     }
 
+    pub fn inference(&self) -> &InferenceConfig {
+        &self.inference
+    }
+
+    pub fn trash_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.trash_retention_days.into())
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.lokai_dir.join("config.toml")
+    }
+
     pub fn logs_dir(&self) -> PathBuf {
         self.lokai_dir.join("logs")
     }
@@ -57,6 +147,14 @@ impl Config {
         self.lokai_dir.join("chats")
     }
 
+    pub fn backups_dir(&self) -> PathBuf {
+        self.lokai_dir.join("backups")
+    }
+
+    pub fn exports_dir(&self) -> PathBuf {
+        self.lokai_dir.join("exports")
+    }
+
     pub fn random_session_path(&self) -> PathBuf {
         self.chats_dir().join(format!("{}.llama", Uuid::new_v4()))
     }
@@ -64,6 +162,14 @@ impl Config {
     pub fn update_database_url(&mut self, database_url: String) {
         self.database_url = database_url;
     }
+
+    pub fn update_inference(&mut self, inference: InferenceConfig) {
+        self.inference.merge(inference);
+    }
+
+    pub fn update_trash_retention_days(&mut self, trash_retention_days: u32) {
+        self.trash_retention_days = trash_retention_days;
+    }
 }
 
 fn create_dir_if_not_exists(path: &PathBuf) {
@@ -71,3 +177,111 @@ fn create_dir_if_not_exists(path: &PathBuf) {
         std::fs::create_dir(path).unwrap_or_else(|_| panic!("cannot create dir: {path:?}"));
     }
 }
+
+/// Reads inference options from `config.toml`, missing file means that nothing is set.
+pub fn read_inference_config(path: &Path) -> AppResult<InferenceConfig> {
+    if !path.exists() {
+        return Ok(Default::default());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("cannot read config {}: {err}", path.display()))?;
+    let inference = toml::from_str(&content)
+        .map_err(|err| format!("cannot parse config {}: {err}", path.display()))?;
+
+    Ok(inference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_inference_config() {
+        // given
+        let content = r#"
+            backend = "ollama"
+            model = "llama3.2"
+            base-url = "http://localhost:11434"
+        "#;
+
+        // when
+        let inference: InferenceConfig = toml::from_str(content).unwrap();
+
+        // then
+        assert_eq!(
+            inference,
+            InferenceConfig {
+                backend: Some(BackendKind::Ollama),
+                model: Some("llama3.2".to_string()),
+                base_url: Some("http://localhost:11434".to_string()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_malformed_inference_config() -> AppResult<()> {
+        // given
+        let dir = std::env::temp_dir().join(format!("lokai-config-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir)?;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "backend = \"mock\"\nmock-token-delay = \"fast\"\n")?;
+
+        // when
+        let result = read_inference_config(&path);
+        std::fs::remove_dir_all(&dir)?;
+
+        // then
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with(&format!("cannot parse config {}:", path.display())));
+        assert!(err.contains("mock-token-delay = \"fast\""));
+        assert!(err.contains("invalid type: string \"fast\", expected u64"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_missing_inference_config() -> AppResult<()> {
+        // given
+        let path = std::env::temp_dir().join(format!("lokai-config-{}.toml", Uuid::new_v4()));
+
+        // when
+        let inference = read_inference_config(&path)?;
+
+        // then
+        assert_eq!(inference, InferenceConfig::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_inference_config() {
+        // given
+        let mut inference = InferenceConfig {
+            backend: Some(BackendKind::Ollama),
+            model: Some("llama3.2".to_string()),
+            mock_token_delay: Some(10),
+            ..Default::default()
+        };
+
+        // when
+        inference.merge(InferenceConfig {
+            model: Some("qwen2.5".to_string()),
+            api_key: Some("secret".to_string()),
+            ..Default::default()
+        });
+
+        // then
+        assert_eq!(
+            inference,
+            InferenceConfig {
+                backend: Some(BackendKind::Ollama),
+                model: Some("qwen2.5".to_string()),
+                api_key: Some("secret".to_string()),
+                mock_token_delay: Some(10),
+                ..Default::default()
+            }
+        );
+    }
+}
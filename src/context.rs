@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::models::{GenerationSettings, Message, Role};
+
+pub const DEFAULT_CONTEXT_SIZE: usize = 4096;
+/// Room left for the response when conversation doesn't limit its length
+pub const DEFAULT_RESPONSE_TOKENS: usize = 512;
+/// Room left for the summary of messages that don't fit into the context
+pub const SUMMARY_TOKENS: usize = 256;
+
+/// What happens with the oldest messages once conversation doesn't fit into model's context.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContextPolicy {
+    /// Drops the oldest messages, system prompt included
+    DropOldest,
+    /// Drops the oldest messages, system prompt is always kept
+    #[default]
+    KeepSystemPrompt,
+    /// Replaces the oldest messages with their summary, system prompt is always kept
+    Summarize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContextWindow {
+    /// Number of tokens model can attend to, prompt and response included
+    pub size: usize,
+    pub policy: ContextPolicy,
+}
+
+impl Default for ContextWindow {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_CONTEXT_SIZE,
+            policy: Default::default(),
+        }
+    }
+}
+
+impl ContextWindow {
+    /// Returns number of tokens available for conversation's history.
+    pub fn budget(&self, settings: &GenerationSettings) -> usize {
+        let response_tokens = settings
+            .max_tokens
+            .map(|max_tokens| max_tokens as usize)
+            .unwrap_or(DEFAULT_RESPONSE_TOKENS);
+        let summary_tokens = match self.policy {
+            ContextPolicy::Summarize => SUMMARY_TOKENS,
+            _ => 0,
+        };
+
+        self.size
+            .saturating_sub(response_tokens)
+            .saturating_sub(summary_tokens)
+    }
+
+    /// Returns ids of messages that don't fit into given budget. The oldest messages are dropped first,
+    /// whole turns at a time, the last message is always kept as it's the one that requires response.
+    pub fn out_of_context(&self, history: &[Message], budget: usize) -> HashSet<u32> {
+        let mut tokens = history.iter().map(message_tokens).sum::<usize>();
+        let mut dropped = HashSet::new();
+        let Some((_, previous_turns)) = history.split_last() else {
+            return dropped;
+        };
+
+        let mut droppable = previous_turns
+            .iter()
+            .filter(|message| {
+                message.role != Role::System || self.policy == ContextPolicy::DropOldest
+            })
+            .peekable();
+        while let Some(message) = droppable.next_if(|message| {
+            // response makes no sense without a prompt, it's dropped together with it
+            tokens > budget || (!dropped.is_empty() && message.role == Role::Assistant)
+        }) {
+            tokens -= message_tokens(message);
+            dropped.insert(message.id);
+        }
+
+        dropped
+    }
+}
+
+/// Returns the latest of summaries that covers some of given messages, together with index
+/// of the last message it covers. Summaries have to be ordered from the most recent one.
+pub fn latest_summary<'a>(
+    summaries: &'a [Message],
+    messages: &[Message],
+) -> Option<(usize, &'a Message)> {
+    messages
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(index, message)| {
+            summaries
+                .iter()
+                .find(|summary| summary.parent_id == Some(message.id))
+                .map(|summary| (index, summary))
+        })
+}
+
+/// Rough number of tokens, used until message gets counted by backend's tokenizer.
+pub fn estimate_tokens(text: &str) -> usize {
+    // English text averages about 4 characters per token
+    text.chars().count().div_ceil(4)
+}
+
+fn message_tokens(message: &Message) -> usize {
+    message
+        .token_count
+        .map(|token_count| token_count as usize)
+        .unwrap_or_else(|| estimate_tokens(&message.content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(id: u32, parent_id: u32) -> Message {
+        Message {
+            parent_id: Some(parent_id),
+            summary: true,
+            ..message(id, Role::System, 10)
+        }
+    }
+
+    fn message(id: u32, role: Role, token_count: u32) -> Message {
+        Message {
+            token_count: Some(token_count),
+            ..Message::new_test(id, role, "")
+        }
+    }
+
+    fn history() -> Vec<Message> {
+        vec![
+            message(1, Role::System, 10),
+            message(2, Role::User, 20),
+            message(3, Role::Assistant, 30),
+            message(4, Role::User, 20),
+            message(5, Role::Assistant, 30),
+            message(6, Role::User, 20),
+        ]
+    }
+
+    #[test]
+    fn test_budget() {
+        let window = ContextWindow {
+            size: 1024,
+            policy: ContextPolicy::Summarize,
+        };
+        let settings = GenerationSettings {
+            max_tokens: Some(128),
+            ..Default::default()
+        };
+
+        assert_eq!(window.budget(&settings), 1024 - 128 - SUMMARY_TOKENS);
+        assert_eq!(window.budget(&Default::default()), 1024 - 512 - 256);
+    }
+
+    #[test]
+    fn test_out_of_context_fits() {
+        let window = ContextWindow::default();
+
+        assert!(window.out_of_context(&history(), 130).is_empty());
+        assert!(window.out_of_context(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn test_out_of_context_keeps_system_prompt() {
+        let window = ContextWindow {
+            size: 0,
+            policy: ContextPolicy::KeepSystemPrompt,
+        };
+
+        assert_eq!(
+            window.out_of_context(&history(), 120),
+            HashSet::from([2, 3])
+        );
+        assert_eq!(
+            window.out_of_context(&history(), 0),
+            HashSet::from([2, 3, 4, 5])
+        );
+    }
+
+    #[test]
+    fn test_out_of_context_drops_oldest() {
+        let window = ContextWindow {
+            size: 0,
+            policy: ContextPolicy::DropOldest,
+        };
+
+        assert_eq!(window.out_of_context(&history(), 125), HashSet::from([1]));
+        assert_eq!(
+            window.out_of_context(&history(), 100),
+            HashSet::from([1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn test_latest_summary() {
+        let history = history();
+        let summaries = vec![summary(9, 3), summary(8, 5), summary(7, 3)];
+
+        assert_eq!(
+            latest_summary(&summaries, &history),
+            Some((4, &summaries[1]))
+        );
+        assert_eq!(
+            latest_summary(&summaries, &history[..4]),
+            Some((2, &summaries[0]))
+        );
+        assert_eq!(latest_summary(&summaries, &history[..2]), None);
+    }
+}
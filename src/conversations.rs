@@ -6,12 +6,18 @@ use std::{
 use crossterm::event::KeyEvent;
 use ratatui::widgets::{List, ListItem, ListState};
 use sqlx::SqlitePool;
-use tui_textarea::TextArea;
+use tui_textarea::{CursorMove, TextArea};
 
-use crate::models::Conversation;
+use crate::{
+    export::ExportFormat,
+    models::{Conversation, GenerationSettings},
+};
 
 pub struct Conversations {
     conversations: Vec<Conversation>,
+    /// Conversations in trash, the most recently deleted go first
+    trashed: Vec<Conversation>,
+    showing_trash: bool,
     pub state: ListState,
     _sqlite: SqlitePool,
 }
@@ -20,42 +26,114 @@ impl Conversations {
     pub fn new(sqlite: SqlitePool) -> Self {
         Self {
             conversations: vec![],
+            trashed: vec![],
+            showing_trash: false,
             state: Default::default(),
             _sqlite: sqlite,
         }
     }
 
-    pub fn delete_conversation(&mut self, conversation: Conversation) {
+    /// List shown in the sidebar, either conversations or trash.
+    fn visible(&self) -> &Vec<Conversation> {
+        match self.showing_trash {
+            true => &self.trashed,
+            false => &self.conversations,
+        }
+    }
+
+    pub fn is_showing_trash(&self) -> bool {
+        self.showing_trash
+    }
+
+    pub fn toggle_trash(&mut self) {
+        self.showing_trash = !self.showing_trash;
+        self.unselect();
+    }
+
+    pub fn set_trashed(&mut self, trashed: Vec<Conversation>) {
+        self.trashed = trashed;
+    }
+
+    /// The most recently deleted conversation, it's restored by undo.
+    pub fn last_trashed(&self) -> Option<Conversation> {
+        self.trashed.first().cloned()
+    }
+
+    pub fn trash_conversation(&mut self, conversation: Conversation) {
         if let Some(index) = self
             .conversations
             .iter()
             .position(|c| c.id == conversation.id)
         {
-            tracing::info!("deleting conversation: {:?}", conversation.id);
+            tracing::info!("moving conversation to trash: {:?}", conversation.id);
             self.conversations.remove(index);
-            if std::fs::remove_file(PathBuf::from(conversation.session_path)).is_err() {
-                tracing::error!(
-                    "cannot delete session for conversation: {:?}",
-                    conversation.id
-                );
-            }
+            self.trashed.insert(0, conversation);
             self.unselect();
         }
     }
 
+    pub fn restore_conversation(&mut self, conversation: Conversation) {
+        if let Some(index) = self.trashed.iter().position(|c| c.id == conversation.id) {
+            tracing::info!("restoring conversation from trash: {:?}", conversation.id);
+            self.trashed.remove(index);
+            let position = self
+                .conversations
+                .iter()
+                .position(|c| c.created_at > conversation.created_at)
+                .unwrap_or(self.conversations.len());
+            self.conversations.insert(position, conversation);
+            self.unselect();
+        }
+    }
+
+    /// Permanently removes conversation together with its session file.
+    pub fn delete_conversation(&mut self, conversation: Conversation) {
+        for conversations in [&mut self.conversations, &mut self.trashed] {
+            if let Some(index) = conversations.iter().position(|c| c.id == conversation.id) {
+                tracing::info!("deleting conversation: {:?}", conversation.id);
+                conversations.remove(index);
+                remove_session_file(&conversation);
+                self.state.select(None);
+                return;
+            }
+        }
+    }
+
+    pub fn update_conversation(&mut self, conversation: Conversation) {
+        if let Some(existing) = self
+            .conversations
+            .iter_mut()
+            .chain(self.trashed.iter_mut())
+            .find(|c| c.id == conversation.id)
+        {
+            *existing = conversation;
+        }
+    }
+
     pub fn set_conversations(&mut self, conversations: Vec<Conversation>) {
         self.conversations = conversations;
     }
 
     pub fn currently_selected(&self) -> Option<Conversation> {
         let selected_index = self.state.selected()?;
-        self.conversations.get(selected_index).cloned()
+        self.visible().get(selected_index).cloned()
     }
 
     pub fn unselect(&mut self) {
         self.state.select(None);
     }
 
+    pub fn select_conversation(&mut self, conversation_id: u32) {
+        self.showing_trash = false;
+        if let Some(index) = self
+            .conversations
+            .iter()
+            .position(|c| c.id == conversation_id)
+        {
+            self.state.select(Some(index));
+        }
+    }
+
     pub fn push(&mut self, conversation: Conversation) {
         self.conversations.push(conversation);
     }
@@ -74,7 +152,7 @@ impl Conversations {
         T: Into<ListItem<'static>>,
     {
         let items = self
-            .conversations
+            .visible()
             .iter()
             .map(|elem| f(elem).into())
             .collect::<Vec<ListItem>>();
@@ -83,9 +161,24 @@ impl Conversations {
     }
 }
 
+/// Removes session file of a permanently deleted conversation, failures are only logged.
+pub fn remove_session_file(conversation: &Conversation) {
+    match std::fs::remove_file(PathBuf::from(&conversation.session_path)) {
+        // session file is created with the first response
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => tracing::error!(
+            "cannot delete session for conversation: {:?}, it's removed by `lokai doctor --fix`: {}",
+            conversation.id,
+            err
+        ),
+        _ => {}
+    }
+}
+
 pub struct NewConversationPopup {
     text: Option<String>,
     text_area: TextArea<'static>,
+    system_prompt_text_area: TextArea<'static>,
+    system_prompt_selected: bool,
     activated: bool,
 }
 
@@ -95,6 +188,8 @@ impl Default for NewConversationPopup {
         Self {
             text: None,
             text_area: Default::default(),
+            system_prompt_text_area: Default::default(),
+            system_prompt_selected: false,
             activated: Default::default(),
         }
     }
@@ -120,6 +215,8 @@ impl NewConversationPopup {
         self.text_area.cut();
         self.text_area.set_yank_text("");
         self.text = None;
+        self.system_prompt_text_area = Default::default();
+        self.system_prompt_selected = false;
     }
 
     pub fn is_activated(&self) -> bool {
@@ -131,6 +228,19 @@ impl NewConversationPopup {
         self.text = Some("".to_string());
     }
 
+    /// Switches input between conversation name and system prompt.
+    pub fn toggle_field(&mut self) {
+        self.system_prompt_selected = !self.system_prompt_selected;
+    }
+
+    pub fn is_system_prompt_selected(&self) -> bool {
+        self.system_prompt_selected
+    }
+
+    pub fn system_prompt_text_area_mut(&mut self) -> &mut TextArea<'static> {
+        &mut self.system_prompt_text_area
+    }
+
     pub fn deactivate(&mut self) {
         self.activated = false;
         self.clear();
@@ -141,6 +251,11 @@ impl NewConversationPopup {
             panic!("Activate popup before you handle input")
         }
 
+        if self.system_prompt_selected {
+            self.system_prompt_text_area.input(key_event);
+            return;
+        }
+
         self.text_area.input(key_event);
         self.text = Some(self.text_area.lines().join("\n").trim().to_string());
     }
@@ -148,6 +263,268 @@ impl NewConversationPopup {
     pub fn get_content(&self) -> Option<&String> {
         self.text.as_ref()
     }
+
+    pub fn get_system_prompt(&self) -> Option<String> {
+        let system_prompt = self
+            .system_prompt_text_area
+            .lines()
+            .join("\n")
+            .trim()
+            .to_string();
+        (!system_prompt.is_empty()).then_some(system_prompt)
+    }
+}
+
+#[derive(Default)]
+pub struct RenameConversationPopup {
+    conversation: Option<Conversation>,
+    text_area: TextArea<'static>,
+}
+
+impl Deref for RenameConversationPopup {
+    type Target = TextArea<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.text_area
+    }
+}
+
+impl RenameConversationPopup {
+    pub fn is_activated(&self) -> bool {
+        self.conversation.is_some()
+    }
+
+    pub fn activate(&mut self, conversation: &Conversation) {
+        self.text_area = TextArea::new(vec![conversation.name.clone()]);
+        self.text_area.move_cursor(CursorMove::End);
+        self.conversation = Some(conversation.clone());
+    }
+
+    pub fn deactivate(&mut self) {
+        self.conversation = None;
+        self.text_area = Default::default();
+    }
+
+    pub fn handle_input(&mut self, key_event: KeyEvent) {
+        if !self.is_activated() {
+            panic!("Activate popup before you handle input")
+        }
+
+        self.text_area.input(key_event);
+    }
+
+    pub fn text_area_mut(&mut self) -> &mut TextArea<'static> {
+        &mut self.text_area
+    }
+
+    /// Conversation that is being renamed.
+    pub fn conversation(&self) -> Option<&Conversation> {
+        self.conversation.as_ref()
+    }
+
+    /// Returns new name, unless it's empty or unchanged.
+    pub fn get_content(&self) -> Option<String> {
+        let name = self.text_area.lines().join(" ").trim().to_string();
+        let original = self.conversation.as_ref()?;
+        (!name.is_empty() && name != original.name).then_some(name)
+    }
+}
+
+/// Exports conversation into a file, directory it's written to can be changed.
+#[derive(Default)]
+pub struct ExportConversationPopup {
+    conversation: Option<Conversation>,
+    text_area: TextArea<'static>,
+    format: ExportFormat,
+    /// Outcome of the last export, path of the created file or an error
+    status: Option<String>,
+}
+
+impl Deref for ExportConversationPopup {
+    type Target = TextArea<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.text_area
+    }
+}
+
+impl ExportConversationPopup {
+    pub fn is_activated(&self) -> bool {
+        self.conversation.is_some()
+    }
+
+    pub fn activate(&mut self, conversation: &Conversation, dir: PathBuf) {
+        self.text_area = TextArea::new(vec![dir.display().to_string()]);
+        self.text_area.move_cursor(CursorMove::End);
+        self.conversation = Some(conversation.clone());
+        self.status = None;
+    }
+
+    pub fn deactivate(&mut self) {
+        self.conversation = None;
+        self.text_area = Default::default();
+        self.status = None;
+    }
+
+    pub fn handle_input(&mut self, key_event: KeyEvent) {
+        if !self.is_activated() {
+            panic!("Activate popup before you handle input")
+        }
+
+        self.text_area.input(key_event);
+    }
+
+    pub fn text_area_mut(&mut self) -> &mut TextArea<'static> {
+        &mut self.text_area
+    }
+
+    /// Conversation that is being exported.
+    pub fn conversation(&self) -> Option<&Conversation> {
+        self.conversation.as_ref()
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Switches to the next format, selected format is kept for the next export.
+    pub fn next_format(&mut self) {
+        self.format = self.format.next();
+    }
+
+    pub fn status(&self) -> Option<&String> {
+        self.status.as_ref()
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = Some(status);
+    }
+
+    /// Returns directory conversation is exported to, unless it's empty.
+    pub fn get_dir(&self) -> Option<PathBuf> {
+        let dir = self.text_area.lines().join("").trim().to_string();
+        (!dir.is_empty()).then(|| PathBuf::from(dir))
+    }
+}
+
+pub const SETTINGS_FIELDS: [&str; 6] = [
+    "Model",
+    "Temperature",
+    "Top P",
+    "Max tokens",
+    "Seed",
+    "System prompt",
+];
+
+#[derive(Default)]
+pub struct ConversationSettingsPopup {
+    fields: [TextArea<'static>; SETTINGS_FIELDS.len()],
+    selected: usize,
+    error: Option<String>,
+    activated: bool,
+}
+
+impl ConversationSettingsPopup {
+    pub fn is_activated(&self) -> bool {
+        self.activated
+    }
+
+    pub fn activate(&mut self, settings: &GenerationSettings, system_prompt: Option<&str>) {
+        let values = [
+            settings.model.clone(),
+            settings.temperature.map(|value| value.to_string()),
+            settings.top_p.map(|value| value.to_string()),
+            settings.max_tokens.map(|value| value.to_string()),
+            settings.seed.map(|value| value.to_string()),
+            system_prompt.map(|value| value.to_string()),
+        ];
+        for (field, value) in self.fields.iter_mut().zip(values) {
+            *field = TextArea::new(vec![value.unwrap_or_default()]);
+            field.move_cursor(CursorMove::End);
+        }
+        self.selected = 0;
+        self.error = None;
+        self.activated = true;
+    }
+
+    pub fn deactivate(&mut self) {
+        self.activated = false;
+        self.fields = Default::default();
+        self.error = None;
+    }
+
+    pub fn handle_input(&mut self, key_event: KeyEvent) {
+        if !self.activated {
+            panic!("Activate popup before you handle input")
+        }
+
+        self.fields[self.selected].input(key_event);
+        self.error = None;
+    }
+
+    pub fn next_field(&mut self) {
+        self.selected = (self.selected + 1) % self.fields.len();
+    }
+
+    pub fn previous_field(&mut self) {
+        self.selected = (self.selected + self.fields.len() - 1) % self.fields.len();
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn fields_mut(&mut self) -> impl Iterator<Item = &mut TextArea<'static>> {
+        self.fields.iter_mut()
+    }
+
+    pub fn error(&self) -> Option<&String> {
+        self.error.as_ref()
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    /// Parses content of the fields, empty field means that backend's default is used.
+    pub fn get_settings(&mut self) -> Option<GenerationSettings> {
+        match self.parse_settings() {
+            Ok(settings) => Some(settings),
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+
+    pub fn get_system_prompt(&self) -> Option<String> {
+        self.field_value(5)
+    }
+
+    fn field_value(&self, index: usize) -> Option<String> {
+        let value = self.fields[index].lines().join("").trim().to_string();
+        (!value.is_empty()).then_some(value)
+    }
+
+    fn parse_field<T: std::str::FromStr>(&self, index: usize) -> Result<Option<T>, String> {
+        self.field_value(index)
+            .map(|value| {
+                value
+                    .parse::<T>()
+                    .map_err(|_| format!("{} has invalid value: {}", SETTINGS_FIELDS[index], value))
+            })
+            .transpose()
+    }
+
+    fn parse_settings(&self) -> Result<GenerationSettings, String> {
+        Ok(GenerationSettings {
+            model: self.field_value(0),
+            temperature: self.parse_field(1)?,
+            top_p: self.parse_field(2)?,
+            max_tokens: self.parse_field(3)?,
+            seed: self.parse_field(4)?,
+        })
+    }
 }
 
 pub struct DeleteConversationPopup {
@@ -244,6 +621,86 @@ mod tests {
         popup.handle_input(KeyEvent::new(KeyCode::Char('H'), KeyModifiers::SHIFT));
     }
 
+    #[test]
+    fn test_handle_input_system_prompt() {
+        // given
+        let mut popup = NewConversationPopup::default();
+        popup.activate();
+        popup.handle_input(KeyEvent::new(KeyCode::Char('J'), KeyModifiers::SHIFT));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::NONE));
+
+        // when
+        popup.toggle_field();
+        popup.handle_input(KeyEvent::new(KeyCode::Char('B'), KeyModifiers::SHIFT));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('e'), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE));
+
+        // then
+        assert_eq!(popup.text, Some("Jo".to_string()));
+        assert_eq!(popup.get_system_prompt(), Some("Be fun".to_string()));
+
+        popup.deactivate();
+        assert_eq!(popup.get_system_prompt(), None);
+        assert!(!popup.is_system_prompt_selected());
+    }
+
+    #[test]
+    fn test_settings_popup_get_settings() {
+        // given
+        let mut popup = ConversationSettingsPopup::default();
+        popup.activate(
+            &GenerationSettings {
+                model: Some("llama-3.2-3b-chat".to_string()),
+                temperature: Some(0.5),
+                ..Default::default()
+            },
+            Some("Be fun"),
+        );
+
+        // when
+        popup.next_field();
+        popup.handle_input(KeyEvent::new(KeyCode::Char('1'), KeyModifiers::NONE));
+        popup.previous_field();
+        popup.previous_field();
+        popup.previous_field();
+        popup.handle_input(KeyEvent::new(KeyCode::Char('4'), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('2'), KeyModifiers::NONE));
+
+        // then
+        assert_eq!(
+            popup.get_settings(),
+            Some(GenerationSettings {
+                model: Some("llama-3.2-3b-chat".to_string()),
+                temperature: Some(0.51),
+                seed: Some(42),
+                ..Default::default()
+            })
+        );
+        assert_eq!(popup.get_system_prompt(), Some("Be fun".to_string()));
+        assert_eq!(popup.error(), None);
+    }
+
+    #[test]
+    fn test_settings_popup_invalid_value() {
+        // given
+        let mut popup = ConversationSettingsPopup::default();
+        popup.activate(&Default::default(), None);
+
+        // when
+        popup.next_field();
+        popup.handle_input(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE));
+
+        // then
+        assert_eq!(popup.get_settings(), None);
+        assert_eq!(
+            popup.error(),
+            Some(&"Temperature has invalid value: x".to_string())
+        );
+    }
+
     #[test]
     fn test_clear() {
         // given
@@ -258,4 +715,109 @@ mod tests {
         assert!(popup.activated);
         assert_eq!(popup.text, None);
     }
+
+    #[sqlx::test]
+    async fn test_trash_and_restore_conversation(pool: SqlitePool) {
+        // given
+        let mut conversations = Conversations::new(pool);
+        let first = Conversation::new_test(1, "first");
+        let second = Conversation {
+            id: 2,
+            created_at: "2024-09-13T09:00:00Z".parse().unwrap(),
+            ..Conversation::new_test(1, "second")
+        };
+        conversations.set_conversations(vec![first.clone(), second.clone()]);
+        conversations.state.select(Some(0));
+
+        // when
+        conversations.trash_conversation(Conversation {
+            deleted_at: Some(Default::default()),
+            ..first.clone()
+        });
+
+        // then
+        assert_eq!(conversations.currently_selected(), None);
+        assert_eq!(conversations.last_trashed().map(|c| c.id), Some(1));
+        conversations.state.select(Some(0));
+        assert_eq!(conversations.currently_selected(), Some(second.clone()));
+
+        conversations.toggle_trash();
+        assert!(conversations.is_showing_trash());
+        conversations.state.select(Some(0));
+        assert_eq!(conversations.currently_selected().map(|c| c.id), Some(1));
+
+        // when
+        conversations.restore_conversation(first.clone());
+
+        // then
+        assert_eq!(conversations.last_trashed(), None);
+        conversations.select_conversation(1);
+        assert!(!conversations.is_showing_trash());
+        assert_eq!(conversations.state.selected(), Some(0));
+        assert_eq!(conversations.currently_selected(), Some(first));
+    }
+
+    #[test]
+    fn test_rename_conversation_popup() {
+        // given
+        let mut popup = RenameConversationPopup::default();
+        let conversation = Conversation::new_test(1, "Sky colou");
+        assert!(!popup.is_activated());
+
+        // when
+        popup.activate(&conversation);
+
+        // then
+        assert!(popup.is_activated());
+        assert_eq!(popup.get_content(), None);
+
+        popup.handle_input(KeyEvent::new(KeyCode::Char('r'), KeyModifiers::NONE));
+        assert_eq!(popup.get_content(), Some("Sky colour".to_string()));
+        assert_eq!(popup.conversation(), Some(&conversation));
+
+        popup.deactivate();
+        assert!(!popup.is_activated());
+        assert_eq!(popup.get_content(), None);
+    }
+
+    #[test]
+    fn test_export_conversation_popup() {
+        // given
+        let mut popup = ExportConversationPopup::default();
+        let conversation = Conversation::new_test(1, "Sky colour");
+        assert!(!popup.is_activated());
+
+        // when
+        popup.activate(&conversation, PathBuf::from("/tmp/exports"));
+
+        // then
+        assert!(popup.is_activated());
+        assert_eq!(popup.conversation(), Some(&conversation));
+        assert_eq!(popup.get_dir(), Some(PathBuf::from("/tmp/exports")));
+        assert_eq!(popup.format(), ExportFormat::Markdown);
+
+        popup.next_format();
+        popup.handle_input(KeyEvent::new(KeyCode::Char('2'), KeyModifiers::NONE));
+        popup.set_status("exported".to_string());
+        assert_eq!(popup.get_dir(), Some(PathBuf::from("/tmp/exports2")));
+        assert_eq!(popup.format(), ExportFormat::Json);
+        assert_eq!(popup.status(), Some(&"exported".to_string()));
+
+        popup.deactivate();
+        assert!(!popup.is_activated());
+        assert_eq!(popup.get_dir(), None);
+        assert_eq!(popup.status(), None);
+        // format is remembered
+        assert_eq!(popup.format(), ExportFormat::Json);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_rename_conversation_popup_handle_input_not_activated() {
+        // given
+        let mut popup = RenameConversationPopup::default();
+
+        // when
+        popup.handle_input(KeyEvent::new(KeyCode::Char('H'), KeyModifiers::SHIFT));
+    }
 }
@@ -1,7 +1,10 @@
+use chrono::{DateTime, Utc};
 use sqlx::{Executor, Sqlite};
 
 use crate::{
-    models::{Conversation, Message, Role},
+    models::{
+        Branch, Conversation, GenerationSettings, Message, MessageEmbedding, Role, SearchHit,
+    },
     AppResult,
 };
 
@@ -24,6 +27,7 @@ where
     Ok(conversation)
 }
 
+/// Returns conversations that aren't in trash.
 pub async fn get_conversations<'e, E>(executor: E) -> AppResult<Vec<Conversation>>
 where
     E: Executor<'e, Database = Sqlite>,
@@ -32,6 +36,7 @@ where
         r#"
         SELECT *
         FROM conversations
+        WHERE deleted_at IS NULL
         ORDER BY created_at ASC
         "#,
     )
@@ -42,6 +47,45 @@ where
     Ok(items)
 }
 
+/// Returns conversations including the ones in trash, their session files still belong to them.
+pub async fn get_all_conversations<'e, E>(executor: E) -> AppResult<Vec<Conversation>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM conversations
+        ORDER BY created_at ASC
+        "#,
+    )
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Returns conversations in trash, the most recently deleted go first.
+pub async fn get_trashed_conversations<'e, E>(executor: E) -> AppResult<Vec<Conversation>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM conversations
+        WHERE deleted_at IS NOT NULL
+        ORDER BY deleted_at DESC, id DESC
+        "#,
+    )
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
 pub async fn create_conversation<'e, E>(
     executor: E,
     name: &str,
@@ -65,6 +109,65 @@ where
     Ok(conversation)
 }
 
+/// Creates conversation that was started in another tool, original creation time is kept.
+pub async fn import_conversation<'e, E>(
+    executor: E,
+    name: &str,
+    session_path: &str,
+    created_at: DateTime<Utc>,
+    settings: &GenerationSettings,
+) -> AppResult<Conversation>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        INSERT INTO conversations(name, session_path, created_at, model, temperature, top_p, max_tokens, seed)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        RETURNING *
+        "#,
+    )
+    .bind(name)
+    .bind(session_path)
+    .bind(sqlite_timestamp(created_at))
+    .bind(&settings.model)
+    .bind(settings.temperature)
+    .bind(settings.top_p)
+    .bind(settings.max_tokens)
+    .bind(settings.seed)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+/// Returns conversation with given name and creation time, it tells whether conversation was already imported.
+pub async fn find_conversation<'e, E>(
+    executor: E,
+    name: &str,
+    created_at: DateTime<Utc>,
+) -> AppResult<Option<Conversation>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM conversations
+        WHERE name = ?1
+            AND created_at = ?2
+        "#,
+    )
+    .bind(name)
+    .bind(sqlite_timestamp(created_at))
+    .persistent(false)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
 pub async fn delete_conversation<'e, E>(
     executor: E,
     conversation_id: u32,
@@ -87,16 +190,202 @@ where
     Ok(conversation)
 }
 
+/// Moves conversation to trash, its messages are kept until it's purged.
+pub async fn trash_conversation<'e, E>(executor: E, conversation_id: u32) -> AppResult<Conversation>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        UPDATE conversations
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE id = ?1
+        RETURNING *
+        "#,
+    )
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+pub async fn restore_conversation<'e, E>(
+    executor: E,
+    conversation_id: u32,
+) -> AppResult<Conversation>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        UPDATE conversations
+        SET deleted_at = NULL
+        WHERE id = ?1
+        RETURNING *
+        "#,
+    )
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+/// Permanently deletes conversations that were moved to trash before given time.
+pub async fn purge_trash<'e, E>(
+    executor: E,
+    deleted_before: DateTime<Utc>,
+) -> AppResult<Vec<Conversation>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        DELETE FROM conversations
+        WHERE deleted_at < ?1
+        RETURNING *
+        "#,
+    )
+    .bind(sqlite_timestamp(deleted_before))
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+pub async fn update_session_path<'e, E>(
+    executor: E,
+    conversation_id: u32,
+    session_path: &str,
+) -> AppResult<Conversation>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        UPDATE conversations
+        SET session_path = ?1
+        WHERE id = ?2
+        RETURNING *
+        "#,
+    )
+    .bind(session_path)
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+/// Names conversation that doesn't have a name yet, so the name given by user is never overwritten.
+/// Returns `None` when conversation is already named.
+pub async fn set_name_if_unnamed<'e, E>(
+    executor: E,
+    conversation_id: u32,
+    name: &str,
+) -> AppResult<Option<Conversation>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        UPDATE conversations
+        SET name = ?1
+        WHERE id = ?2
+            AND name = ''
+        RETURNING *
+        "#,
+    )
+    .bind(name)
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+pub async fn update_conversation_name<'e, E>(
+    executor: E,
+    conversation_id: u32,
+    name: &str,
+) -> AppResult<Conversation>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        UPDATE conversations
+        SET name = ?1
+        WHERE id = ?2
+        RETURNING *
+        "#,
+    )
+    .bind(name)
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+pub async fn update_conversation_settings<'e, E>(
+    executor: E,
+    conversation_id: u32,
+    settings: &GenerationSettings,
+) -> AppResult<Conversation>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        UPDATE conversations
+        SET model = ?1, temperature = ?2, top_p = ?3, max_tokens = ?4, seed = ?5
+        WHERE id = ?6
+        RETURNING *
+        "#,
+    )
+    .bind(&settings.model)
+    .bind(settings.temperature)
+    .bind(settings.top_p)
+    .bind(settings.max_tokens)
+    .bind(settings.seed)
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+/// Returns messages of conversation's active branch, system prompt goes first.
 pub async fn get_messages<'e, E>(executor: E, conversation_id: u32) -> AppResult<Vec<Message>>
 where
     E: Executor<'e, Database = Sqlite>,
 {
     let items = sqlx::query_as(
         r#"
+        WITH RECURSIVE path(id) AS (
+            SELECT active_message_id
+            FROM conversations
+            WHERE id = ?1
+            UNION ALL
+            SELECT m.parent_id
+            FROM messages m
+            JOIN path p ON m.id = p.id
+            WHERE m.parent_id IS NOT NULL
+        )
         SELECT *
         FROM messages
         WHERE conversation_id = ?1
-        ORDER BY created_at ASC
+            AND ((role = 'system' AND NOT summary) OR id IN (SELECT id FROM path))
+        ORDER BY created_at ASC, id ASC
         "#,
     )
     .bind(conversation_id)
@@ -107,6 +396,37 @@ where
     Ok(items)
 }
 
+/// Returns messages that lead to given message (inclusive), system prompt goes first.
+pub async fn get_messages_until<'e, E>(executor: E, message_id: u32) -> AppResult<Vec<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        WITH RECURSIVE path(id) AS (
+            SELECT ?1
+            UNION ALL
+            SELECT m.parent_id
+            FROM messages m
+            JOIN path p ON m.id = p.id
+            WHERE m.parent_id IS NOT NULL
+        )
+        SELECT *
+        FROM messages
+        WHERE conversation_id = (SELECT conversation_id FROM messages WHERE id = ?1)
+            AND ((role = 'system' AND NOT summary) OR id IN (SELECT id FROM path))
+        ORDER BY created_at ASC, id ASC
+        "#,
+    )
+    .bind(message_id)
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Appends message to conversation's active branch.
 pub async fn create_message<'e, E>(
     executor: E,
     role: Role,
@@ -118,49 +438,669 @@ where
 {
     let new_message: Message = sqlx::query_as(
         r#"
-        INSERT INTO messages(role, content, conversation_id)
-        VALUES (?1, ?2, ?3)
+        INSERT INTO messages(role, content, conversation_id, parent_id)
+        SELECT ?1, ?2, id, active_message_id
+        FROM conversations
+        WHERE id = ?3
+        RETURNING *
+    "#,
+    )
+    .bind(role.to_string())
+    .bind(content)
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(new_message)
+}
+
+/// Creates message that was written in another tool, original creation time is kept.
+/// Like any other new message it becomes the leaf of conversation's active branch.
+pub async fn import_message<'e, E>(
+    executor: E,
+    role: Role,
+    content: &str,
+    conversation_id: u32,
+    parent_id: Option<u32>,
+    created_at: DateTime<Utc>,
+) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let new_message: Message = sqlx::query_as(
+        r#"
+        INSERT INTO messages(role, content, conversation_id, parent_id, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
         RETURNING *
     "#,
     )
-    .bind(role.to_string())
-    .bind(content)
-    .bind(conversation_id)
+    .bind(role.to_string())
+    .bind(content)
+    .bind(conversation_id)
+    .bind(parent_id)
+    .bind(sqlite_timestamp(created_at))
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(new_message)
+}
+
+/// Creates message that follows up given parent, new branch is started when parent already
+/// has follow-ups. Either way the message becomes the leaf of conversation's active branch.
+pub async fn create_child_message<'e, E>(
+    executor: E,
+    role: Role,
+    content: &str,
+    conversation_id: u32,
+    parent_id: Option<u32>,
+) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let new_message: Message = sqlx::query_as(
+        r#"
+        INSERT INTO messages(role, content, conversation_id, parent_id)
+        VALUES (?1, ?2, ?3, ?4)
+        RETURNING *
+    "#,
+    )
+    .bind(role.to_string())
+    .bind(content)
+    .bind(conversation_id)
+    .bind(parent_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(new_message)
+}
+
+/// Returns the most recent assistant's response in a conversation, regardless of its branch.
+pub async fn get_last_response<'e, E>(
+    executor: E,
+    conversation_id: u32,
+) -> AppResult<Option<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let last_response = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM messages
+        WHERE conversation_id = ?1
+            AND role = 'assistant'
+        ORDER BY created_at DESC, id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(last_response)
+}
+
+pub async fn update_message<'e, E>(
+    executor: E,
+    content: &str,
+    message_id: u32,
+) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let updated_message: Message = sqlx::query_as(
+        r#"
+        UPDATE messages
+        SET content = ?1, token_count = NULL
+        WHERE id = ?2
+        RETURNING *
+        "#,
+    )
+    .bind(content)
+    .bind(message_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(updated_message)
+}
+
+/// Saves partial content of a message which generation was cancelled.
+pub async fn interrupt_message<'e, E>(
+    executor: E,
+    content: &str,
+    message_id: u32,
+) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let interrupted_message: Message = sqlx::query_as(
+        r#"
+        UPDATE messages
+        SET content = ?1, interrupted = TRUE, token_count = NULL
+        WHERE id = ?2
+        RETURNING *
+        "#,
+    )
+    .bind(content)
+    .bind(message_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(interrupted_message)
+}
+
+pub async fn update_token_count<'e, E>(
+    executor: E,
+    token_count: u32,
+    message_id: u32,
+) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let updated_message: Message = sqlx::query_as(
+        r#"
+        UPDATE messages
+        SET token_count = ?1
+        WHERE id = ?2
+        RETURNING *
+        "#,
+    )
+    .bind(token_count)
+    .bind(message_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(updated_message)
+}
+
+/// Returns all versions of given message (including itself), i.e. messages with the same parent.
+pub async fn list_branches<'e, E>(executor: E, message_id: u32) -> AppResult<Vec<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT s.*
+        FROM messages s
+        JOIN messages m ON m.id = ?1
+        WHERE s.conversation_id = m.conversation_id
+            AND s.role != 'system'
+            AND s.parent_id IS m.parent_id
+        ORDER BY s.created_at ASC, s.id ASC
+        "#,
+    )
+    .bind(message_id)
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Makes branch that goes through given message active, it continues with the most recent
+/// follow-ups of the message.
+pub async fn switch_branch<'e, E>(executor: E, message_id: u32) -> AppResult<Conversation>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversation = sqlx::query_as(
+        r#"
+        WITH RECURSIVE descendants(id, depth) AS (
+            SELECT id, 0
+            FROM messages
+            WHERE id = ?1
+            UNION ALL
+            SELECT (
+                SELECT c.id
+                FROM messages c
+                WHERE c.parent_id = d.id
+                    AND NOT c.summary
+                ORDER BY c.created_at DESC, c.id DESC
+                LIMIT 1
+            ), d.depth + 1
+            FROM descendants d
+            WHERE EXISTS (SELECT 1 FROM messages c WHERE c.parent_id = d.id AND NOT c.summary)
+        )
+        UPDATE conversations
+        SET active_message_id = (SELECT id FROM descendants ORDER BY depth DESC LIMIT 1)
+        WHERE id = (SELECT conversation_id FROM messages WHERE id = ?1)
+        RETURNING *
+        "#,
+    )
+    .bind(message_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(conversation)
+}
+
+/// Returns positions of all messages in a conversation that have more than one version.
+pub async fn get_branches<'e, E>(executor: E, conversation_id: u32) -> AppResult<Vec<Branch>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT message_id, position, count
+        FROM (
+            SELECT
+                id AS message_id,
+                ROW_NUMBER() OVER (PARTITION BY parent_id ORDER BY created_at ASC, id ASC) AS position,
+                COUNT(*) OVER (PARTITION BY parent_id) AS count
+            FROM messages
+            WHERE conversation_id = ?1
+                AND role != 'system'
+        )
+        WHERE count > 1
+        "#,
+    )
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+pub async fn delete_message<'e, E>(executor: E, message_id: u32) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let deleted_message: Message = sqlx::query_as(
+        r#"
+        DELETE FROM messages
+        WHERE id = ?1
+        RETURNING *
+        "#,
+    )
+    .bind(message_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(deleted_message)
+}
+
+pub async fn get_system_prompt<'e, E>(
+    executor: E,
+    conversation_id: u32,
+) -> AppResult<Option<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let system_prompt = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM messages
+        WHERE conversation_id = ?1
+            AND role = 'system'
+            AND NOT summary
+        ORDER BY created_at ASC, id ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(system_prompt)
+}
+
+/// Creates system message that has the same timestamp as its conversation,
+/// so it's the first message even when conversation already has some messages.
+pub async fn create_system_prompt<'e, E>(
+    executor: E,
+    content: &str,
+    conversation_id: u32,
+) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let new_message: Message = sqlx::query_as(
+        r#"
+        INSERT INTO messages(role, content, conversation_id, created_at)
+        SELECT ?1, ?2, id, created_at
+        FROM conversations
+        WHERE id = ?3
+        RETURNING *
+        "#,
+    )
+    .bind(Role::System.to_string())
+    .bind(content)
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(new_message)
+}
+
+/// Searches content of all messages, the best matches go first. Every word of the query has to match,
+/// the last one may be incomplete as the query is typed.
+pub async fn search_messages<'e, E>(
+    executor: E,
+    query: &str,
+    limit: u32,
+) -> AppResult<Vec<SearchHit>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let Some(match_query) = fts_query(query) else {
+        return Ok(vec![]);
+    };
+
+    let items = sqlx::query_as(
+        r#"
+        SELECT
+            m.id AS message_id,
+            m.conversation_id,
+            c.name AS conversation_name,
+            snippet(messages_fts, 0, '[', ']', '…', 12) AS snippet
+        FROM messages_fts
+        JOIN messages m ON m.id = messages_fts.rowid
+        JOIN conversations c ON c.id = m.conversation_id
+        WHERE messages_fts MATCH ?1
+            AND NOT m.summary
+            AND c.deleted_at IS NULL
+        ORDER BY bm25(messages_fts) ASC, m.created_at DESC
+        LIMIT ?2
+        "#,
+    )
+    .bind(match_query)
+    .bind(limit)
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Formats timestamp the same way as `CURRENT_TIMESTAMP`, so imported rows are ordered together with the others.
+fn sqlite_timestamp(datetime: DateTime<Utc>) -> String {
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Turns user's query into FTS5 query, so special characters are matched literally.
+fn fts_query(query: &str) -> Option<String> {
+    let terms = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+
+    (!terms.is_empty()).then(|| format!("{}*", terms.join(" ")))
+}
+
+/// Returns user and assistant messages that weren't embedded by given model yet.
+pub async fn get_messages_without_embedding<'e, E>(
+    executor: E,
+    model: &str,
+) -> AppResult<Vec<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT m.*
+        FROM messages m
+        LEFT JOIN message_embeddings e ON e.message_id = m.id AND e.model = ?1
+        WHERE m.role != 'system'
+            AND e.message_id IS NULL
+        ORDER BY m.id ASC
+        "#,
+    )
+    .bind(model)
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Stores embedding of a message, embedding of another model gets replaced.
+pub async fn upsert_embedding<'e, E>(
+    executor: E,
+    message_id: u32,
+    model: &str,
+    embedding: &[f32],
+) -> AppResult<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let bytes = embedding
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<u8>>();
+
+    sqlx::query(
+        r#"
+        INSERT INTO message_embeddings(message_id, model, embedding)
+        VALUES(?1, ?2, ?3)
+        ON CONFLICT(message_id) DO UPDATE SET model = excluded.model, embedding = excluded.embedding
+        "#,
+    )
+    .bind(message_id)
+    .bind(model)
+    .bind(bytes)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns embeddings of all messages produced by given model.
+pub async fn get_embeddings<'e, E>(executor: E, model: &str) -> AppResult<Vec<MessageEmbedding>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT e.message_id, m.conversation_id, e.embedding
+        FROM message_embeddings e
+        JOIN messages m ON m.id = e.message_id
+        WHERE e.model = ?1
+        ORDER BY e.message_id ASC
+        "#,
+    )
+    .bind(model)
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Returns summaries of conversation's messages, the most recent ones go first.
+pub async fn get_summaries<'e, E>(executor: E, conversation_id: u32) -> AppResult<Vec<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM messages
+        WHERE conversation_id = ?1
+            AND summary
+        ORDER BY created_at DESC, id DESC
+        "#,
+    )
+    .bind(conversation_id)
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Creates summary of messages up to and including given parent. It doesn't become
+/// a part of any branch, as it's only fed to the model instead of messages it covers.
+pub async fn create_summary<'e, E>(
+    executor: E,
+    content: &str,
+    conversation_id: u32,
+    parent_id: u32,
+) -> AppResult<Message>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let new_message: Message = sqlx::query_as(
+        r#"
+        INSERT INTO messages(role, content, conversation_id, parent_id, summary)
+        VALUES (?1, ?2, ?3, ?4, TRUE)
+        RETURNING *
+        "#,
+    )
+    .bind(Role::System.to_string())
+    .bind(content)
+    .bind(conversation_id)
+    .bind(parent_id)
+    .persistent(false)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(new_message)
+}
+
+/// Returns conversations that have at least one prompt or response, their session files
+/// are expected to exist.
+pub async fn get_conversations_with_messages<'e, E>(executor: E) -> AppResult<Vec<Conversation>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT c.*
+        FROM conversations c
+        WHERE EXISTS (
+            SELECT 1
+            FROM messages m
+            WHERE m.conversation_id = c.id
+                AND m.role != 'system'
+        )
+        ORDER BY c.created_at ASC
+        "#,
+    )
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Returns messages of conversations that no longer exist, they're left behind when
+/// conversation was deleted without foreign keys being enforced.
+pub async fn get_dangling_messages<'e, E>(executor: E) -> AppResult<Vec<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM messages
+        WHERE conversation_id NOT IN (SELECT id FROM conversations)
+        ORDER BY id ASC
+        "#,
+    )
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+pub async fn delete_dangling_messages<'e, E>(executor: E) -> AppResult<Vec<Message>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        DELETE FROM messages
+        WHERE conversation_id NOT IN (SELECT id FROM conversations)
+        RETURNING *
+        "#,
+    )
+    .persistent(false)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(items)
+}
+
+/// Returns conversations whose active branch doesn't lead to any of their messages.
+pub async fn get_broken_branches<'e, E>(executor: E) -> AppResult<Vec<Conversation>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let items = sqlx::query_as(
+        r#"
+        SELECT c.*
+        FROM conversations c
+        WHERE CASE
+            WHEN c.active_message_id IS NULL THEN EXISTS (
+                SELECT 1
+                FROM messages m
+                WHERE m.conversation_id = c.id
+                    AND m.role != 'system'
+            )
+            ELSE NOT EXISTS (
+                SELECT 1
+                FROM messages m
+                WHERE m.id = c.active_message_id
+                    AND m.conversation_id = c.id
+            )
+        END
+        ORDER BY c.created_at ASC
+        "#,
+    )
     .persistent(false)
-    .fetch_one(executor)
+    .fetch_all(executor)
     .await?;
 
-    Ok(new_message)
+    Ok(items)
 }
 
-pub async fn update_message<'e, E>(
+/// Makes the most recent message of conversation the leaf of its active branch.
+pub async fn reset_active_branch<'e, E>(
     executor: E,
-    content: &str,
-    message_id: u32,
-) -> AppResult<Message>
+    conversation_id: u32,
+) -> AppResult<Conversation>
 where
     E: Executor<'e, Database = Sqlite>,
 {
-    let updated_message: Message = sqlx::query_as(
+    let conversation = sqlx::query_as(
         r#"
-        UPDATE messages
-        SET content = ?1
-        WHERE id = ?2
+        UPDATE conversations
+        SET active_message_id = (
+            SELECT m.id
+            FROM messages m
+            WHERE m.conversation_id = conversations.id
+                AND m.role != 'system'
+            ORDER BY m.created_at DESC, m.id DESC
+            LIMIT 1
+        )
+        WHERE id = ?1
         RETURNING *
         "#,
     )
-    .bind(content)
-    .bind(message_id)
+    .bind(conversation_id)
     .persistent(false)
     .fetch_one(executor)
     .await?;
 
-    Ok(updated_message)
+    Ok(conversation)
 }
 
 #[cfg(test)]
 mod tests {
     use chrono::DateTime;
+    use rstest::rstest;
     use sqlx::{Row, SqlitePool};
 
     use crate::models::Role;
@@ -212,7 +1152,9 @@ mod tests {
                     session_path: "~/.lokai/chats/1".to_string(),
                     created_at: DateTime::parse_from_rfc3339("2024-09-13T09:00:00Z")
                         .unwrap()
-                        .into()
+                        .into(),
+                    deleted_at: None,
+                    settings: Default::default(),
                 },
                 Conversation {
                     id: 2,
@@ -220,7 +1162,9 @@ mod tests {
                     session_path: "~/.lokai/chats/2".to_string(),
                     created_at: DateTime::parse_from_rfc3339("2024-09-13T09:00:59Z")
                         .unwrap()
-                        .into()
+                        .into(),
+                    deleted_at: None,
+                    settings: Default::default(),
                 },
                 Conversation {
                     id: 3,
@@ -228,7 +1172,9 @@ mod tests {
                     session_path: "~/.lokai/chats/3".to_string(),
                     created_at: DateTime::parse_from_rfc3339("2024-09-13T09:01:00Z")
                         .unwrap()
-                        .into()
+                        .into(),
+                    deleted_at: None,
+                    settings: Default::default(),
                 },
                 Conversation {
                     id: 4,
@@ -236,7 +1182,9 @@ mod tests {
                     session_path: "~/.lokai/chats/4".to_string(),
                     created_at: DateTime::parse_from_rfc3339("2024-09-13T09:01:00Z")
                         .unwrap()
-                        .into()
+                        .into(),
+                    deleted_at: None,
+                    settings: Default::default(),
                 },
             ]
         );
@@ -287,7 +1235,9 @@ mod tests {
                 session_path: "~/.lokai/chats/1".to_string(),
                 created_at: DateTime::parse_from_rfc3339("2024-09-13T09:00:00Z")
                     .unwrap()
-                    .into()
+                    .into(),
+                deleted_at: None,
+                settings: Default::default(),
             }
         );
 
@@ -309,6 +1259,148 @@ mod tests {
         Ok(())
     }
 
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_trash_conversation(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let ids = |conversations: Vec<Conversation>| {
+            conversations
+                .into_iter()
+                .map(|conversation| conversation.id)
+                .collect::<Vec<_>>()
+        };
+
+        // when
+        let trashed = trash_conversation(&pool, 1).await?;
+        sqlx::query("UPDATE conversations SET deleted_at = '2024-09-13 09:00:00' WHERE id = 2")
+            .execute(&pool)
+            .await?;
+
+        // then
+        assert!(trashed.deleted_at.is_some());
+        assert_eq!(ids(get_conversations(&pool).await?), vec![3, 4]);
+        assert_eq!(ids(get_trashed_conversations(&pool).await?), vec![1, 2]);
+        assert_eq!(ids(get_all_conversations(&pool).await?), vec![1, 2, 3, 4]);
+        assert!(search_messages(&pool, "sky", 10).await?.is_empty());
+
+        // when
+        let purged = purge_trash(
+            &pool,
+            DateTime::parse_from_rfc3339("2024-09-14T00:00:00Z")?.into(),
+        )
+        .await?;
+
+        // then
+        assert_eq!(ids(purged), vec![2]);
+        assert_eq!(table_count(&pool, "messages").await?, 2);
+
+        // when
+        let restored = restore_conversation(&pool, 1).await?;
+
+        // then
+        assert_eq!(restored.deleted_at, None);
+        assert_eq!(ids(get_conversations(&pool).await?), vec![1, 3, 4]);
+        assert!(get_trashed_conversations(&pool).await?.is_empty());
+        assert_eq!(search_messages(&pool, "sky", 10).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql"))]
+    async fn test_update_conversation_name(pool: SqlitePool) -> AppResult<()> {
+        // given
+        assert_eq!(get_conversation(&pool, 1).await?.name, "conversation 1");
+
+        // when
+        let updated_conversation = update_conversation_name(&pool, 1, "Sky colour").await?;
+
+        // then
+        assert_eq!(updated_conversation.id, 1);
+        assert_eq!(updated_conversation.name, "Sky colour");
+        assert_eq!(get_conversation(&pool, 1).await?, updated_conversation);
+        assert_eq!(get_conversation(&pool, 2).await?.name, "conversation 2");
+        assert!(update_conversation_name(&pool, 9999, "Sky colour")
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql"))]
+    async fn test_set_name_if_unnamed(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let unnamed_conversation = create_conversation(&pool, "", "~/.lokai/chats/5").await?;
+
+        // when
+        let renamed_conversation =
+            set_name_if_unnamed(&pool, unnamed_conversation.id, "Sky colour").await?;
+
+        // then
+        assert_eq!(
+            renamed_conversation,
+            Some(Conversation {
+                name: "Sky colour".to_string(),
+                ..unnamed_conversation
+            })
+        );
+        // named conversations are left untouched
+        assert_eq!(set_name_if_unnamed(&pool, 1, "Sky colour").await?, None);
+        assert_eq!(get_conversation(&pool, 1).await?.name, "conversation 1");
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql"))]
+    async fn test_update_conversation_settings(pool: SqlitePool) -> AppResult<()> {
+        // given
+        assert_eq!(table_count(&pool, "conversations").await?, 4);
+        let settings = GenerationSettings {
+            model: Some("llama-3.2-3b-chat".to_string()),
+            temperature: Some(0.5),
+            top_p: None,
+            max_tokens: Some(512),
+            seed: Some(42),
+        };
+
+        // when
+        let updated_conversation = update_conversation_settings(&pool, 2, &settings).await?;
+
+        // then
+        assert_eq!(table_count(&pool, "conversations").await?, 4);
+        assert_eq!(
+            updated_conversation,
+            Conversation {
+                id: 2,
+                name: "conversation 2".to_string(),
+                session_path: "~/.lokai/chats/2".to_string(),
+                created_at: DateTime::parse_from_rfc3339("2024-09-13T09:00:59Z")
+                    .unwrap()
+                    .into(),
+                deleted_at: None,
+                settings: settings.clone(),
+            }
+        );
+        assert_eq!(get_conversation(&pool, 2).await?.settings, settings);
+        assert_eq!(
+            get_conversation(&pool, 1).await?.settings,
+            GenerationSettings::default()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql"))]
+    async fn test_update_conversation_settings_that_doesnt_exist(
+        pool: SqlitePool,
+    ) -> AppResult<()> {
+        // when
+        let result = update_conversation_settings(&pool, 9999, &Default::default()).await;
+
+        // then
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
     #[sqlx::test(fixtures("../fixtures/conversations.sql"))]
     async fn test_get_messages_empty_table(pool: SqlitePool) -> AppResult<()> {
         // given
@@ -342,18 +1434,26 @@ mod tests {
                     role: Role::User,
                     content: "why is the sky blue?".to_string(),
                     conversation_id: 1,
+                    parent_id: None,
                     created_at: DateTime::parse_from_rfc3339("2024-09-13T09:00:00Z")
                         .unwrap()
-                        .into()
+                        .into(),
+                    interrupted: false,
+                    token_count: None,
+                    summary: false,
                 },
                 Message {
                     id: 2,
                     role: Role::Assistant,
                     content: "I don't know".to_string(),
                     conversation_id: 1,
+                    parent_id: Some(1),
                     created_at: DateTime::parse_from_rfc3339("2024-09-13T09:00:59Z")
                         .unwrap()
-                        .into()
+                        .into(),
+                    interrupted: false,
+                    token_count: None,
+                    summary: false,
                 },
             ]
         );
@@ -381,6 +1481,8 @@ mod tests {
 
         assert!(new_message_1.id < new_message_2.id);
         assert_eq!(new_message_1.created_at, new_message_2.created_at);
+        assert_eq!(new_message_1.parent_id, None);
+        assert_eq!(new_message_2.parent_id, Some(new_message_1.id));
 
         assert_eq!(new_message_1.role, Role::User);
         assert_eq!(new_message_1.content, "why is the sky blue?".to_string());
@@ -437,9 +1539,13 @@ mod tests {
                 role: Role::Assistant,
                 content: "I don't know".to_string(),
                 conversation_id: 1,
+                parent_id: Some(1),
                 created_at: DateTime::parse_from_rfc3339("2024-09-13T09:00:59Z")
                     .unwrap()
-                    .into()
+                    .into(),
+                interrupted: false,
+                token_count: None,
+                summary: false,
             }
         );
 
@@ -464,6 +1570,335 @@ mod tests {
         Ok(())
     }
 
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_interrupt_message(pool: SqlitePool) -> AppResult<()> {
+        // given
+        assert_eq!(table_count(&pool, "messages").await?, 5);
+
+        // when
+        let interrupted_message = interrupt_message(&pool, "Because of", 2).await?;
+
+        // then
+        assert_eq!(table_count(&pool, "messages").await?, 5);
+        assert_eq!(interrupted_message.id, 2);
+        assert_eq!(interrupted_message.content, "Because of");
+        assert!(interrupted_message.interrupted);
+        assert_eq!(get_messages(&pool, 1).await?[1], interrupted_message);
+        assert!(interrupt_message(&pool, "Because of", 10).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_update_token_count(pool: SqlitePool) -> AppResult<()> {
+        // when
+        let counted_message = update_token_count(&pool, 4, 2).await?;
+
+        // then
+        assert_eq!(counted_message.token_count, Some(4));
+        assert_eq!(get_messages(&pool, 1).await?[1], counted_message);
+        // content changes invalidate token count
+        let updated_message = update_message(&pool, "Because of Rayleigh scattering", 2).await?;
+        assert_eq!(updated_message.token_count, None);
+        assert!(update_token_count(&pool, 4, 10).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_branches(pool: SqlitePool) -> AppResult<()> {
+        // given
+        assert!(get_branches(&pool, 1).await?.is_empty());
+        assert_eq!(list_branches(&pool, 2).await?.len(), 1);
+
+        // when
+        let new_response =
+            create_child_message(&pool, Role::Assistant, "Rayleigh scattering", 1, Some(1)).await?;
+
+        // then
+        let messages = get_messages(&pool, 1).await?;
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+            vec![1, new_response.id]
+        );
+        assert_eq!(
+            list_branches(&pool, 2)
+                .await?
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+            vec![2, new_response.id]
+        );
+        assert_eq!(
+            get_branches(&pool, 1).await?,
+            vec![
+                Branch {
+                    message_id: 2,
+                    position: 1,
+                    count: 2
+                },
+                Branch {
+                    message_id: new_response.id,
+                    position: 2,
+                    count: 2
+                },
+            ]
+        );
+        assert_eq!(
+            get_messages_until(&pool, 2)
+                .await?
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(get_branches(&pool, 2).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_switch_branch(pool: SqlitePool) -> AppResult<()> {
+        // given
+        create_system_prompt(&pool, "Be fun", 2).await?;
+        let new_prompt =
+            create_child_message(&pool, Role::User, "tell me a story", 2, None).await?;
+        let new_response = create_message(&pool, Role::Assistant, "Once upon a time", 2).await?;
+        assert_eq!(new_response.parent_id, Some(new_prompt.id));
+
+        // when
+        switch_branch(&pool, 3).await?;
+
+        // then
+        let messages = get_messages(&pool, 2).await?;
+        assert_eq!(messages[0].role, Role::System);
+        assert_eq!(
+            messages[1..]
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+
+        switch_branch(&pool, new_prompt.id).await?;
+        let messages = get_messages(&pool, 2).await?;
+        assert_eq!(
+            messages[1..]
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+            vec![new_prompt.id, new_response.id]
+        );
+        assert!(switch_branch(&pool, 9999).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_get_last_response(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let new_response =
+            create_child_message(&pool, Role::Assistant, "Rayleigh scattering", 1, Some(1)).await?;
+        switch_branch(&pool, 2).await?;
+
+        // when
+        let last_response = get_last_response(&pool, 1).await?;
+
+        // then
+        assert_eq!(last_response, Some(new_response));
+        assert_eq!(get_last_response(&pool, 3).await?, None);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_search_messages(pool: SqlitePool) -> AppResult<()> {
+        // when
+        let hits = search_messages(&pool, "know", 10).await?;
+
+        // then
+        assert_eq!(
+            hits,
+            vec![
+                SearchHit {
+                    message_id: 2,
+                    conversation_id: 1,
+                    conversation_name: "conversation 1".to_string(),
+                    snippet: "I don't [know]".to_string(),
+                },
+                SearchHit {
+                    message_id: 4,
+                    conversation_id: 2,
+                    conversation_name: "conversation 2".to_string(),
+                    snippet: "I don't [know] any jokes".to_string(),
+                },
+            ]
+        );
+        assert_eq!(search_messages(&pool, "know", 1).await?.len(), 1);
+        // the last word is matched as a prefix
+        let hits = search_messages(&pool, "any jok", 10).await?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "I don't know [any] [jokes]");
+        assert!(search_messages(&pool, "  ", 10).await?.is_empty());
+        assert!(search_messages(&pool, "\"sky* OR", 10).await?.is_empty());
+
+        // index follows content changes
+        update_message(&pool, "Because of Rayleigh scattering", 2).await?;
+        assert_eq!(search_messages(&pool, "rayleigh", 10).await?.len(), 1);
+        assert_eq!(search_messages(&pool, "know", 10).await?.len(), 1);
+        delete_conversation(&pool, 1).await?;
+        assert!(search_messages(&pool, "rayleigh", 10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_embeddings(pool: SqlitePool) -> AppResult<()> {
+        // given
+        create_system_prompt(&pool, "Be fun", 1).await?;
+
+        // when
+        upsert_embedding(&pool, 1, "bert", &[0.5, -1.0]).await?;
+        upsert_embedding(&pool, 3, "bert", &[1.0, 0.25]).await?;
+        upsert_embedding(&pool, 4, "other", &[1.0]).await?;
+
+        // then
+        assert_eq!(
+            get_embeddings(&pool, "bert").await?,
+            vec![
+                MessageEmbedding {
+                    message_id: 1,
+                    conversation_id: 1,
+                    embedding: vec![0.5, -1.0],
+                },
+                MessageEmbedding {
+                    message_id: 3,
+                    conversation_id: 2,
+                    embedding: vec![1.0, 0.25],
+                },
+            ]
+        );
+        let ids = |messages: Vec<Message>| messages.iter().map(|m| m.id).collect::<Vec<_>>();
+        // system prompts aren't embedded
+        assert_eq!(
+            ids(get_messages_without_embedding(&pool, "bert").await?),
+            vec![2, 4, 5]
+        );
+
+        // embedding of edited message is outdated
+        update_message(&pool, "why is the sky so blue?", 1).await?;
+        upsert_embedding(&pool, 4, "bert", &[0.0, 1.0]).await?;
+        assert_eq!(
+            ids(get_messages_without_embedding(&pool, "bert").await?),
+            vec![1, 2, 5]
+        );
+        assert_eq!(
+            ids(get_messages_without_embedding(&pool, "other").await?),
+            vec![1, 2, 3, 4, 5]
+        );
+        delete_conversation(&pool, 2).await?;
+        assert!(get_embeddings(&pool, "bert").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("", None)]
+    #[case("sky", Some("\"sky\"*"))]
+    #[case(" sky  blue ", Some("\"sky\" \"blue\"*"))]
+    #[case("\"sky* OR", Some("\"\"\"sky*\" \"OR\"*"))]
+    fn test_fts_query(#[case] query: &str, #[case] expected: Option<&str>) {
+        assert_eq!(fts_query(query).as_deref(), expected);
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_summaries(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let messages = get_messages(&pool, 2).await?;
+
+        // when
+        let first_summary = create_summary(&pool, "User asked for a joke", 2, 3).await?;
+        let second_summary = create_summary(&pool, "Assistant didn't know any", 2, 4).await?;
+
+        // then
+        assert_eq!(first_summary.role, Role::System);
+        assert!(first_summary.summary);
+        assert_eq!(second_summary.parent_id, Some(4));
+        assert_eq!(
+            get_summaries(&pool, 2).await?,
+            vec![second_summary, first_summary]
+        );
+        assert!(get_summaries(&pool, 1).await?.is_empty());
+        // summaries are neither part of the branch nor its versions
+        assert_eq!(get_messages(&pool, 2).await?, messages);
+        assert!(get_branches(&pool, 2).await?.is_empty());
+        assert_eq!(get_system_prompt(&pool, 2).await?, None);
+        switch_branch(&pool, 3).await?;
+        assert_eq!(get_messages(&pool, 2).await?, messages);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_delete_message(pool: SqlitePool) -> AppResult<()> {
+        // given
+        assert_eq!(table_count(&pool, "messages").await?, 5);
+
+        // when
+        let deleted_message = delete_message(&pool, 2).await?;
+
+        // then
+        assert_eq!(table_count(&pool, "messages").await?, 4);
+        assert_eq!(deleted_message.id, 2);
+        assert_eq!(deleted_message.content, "I don't know");
+        assert!(delete_message(&pool, 2).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_create_system_prompt(pool: SqlitePool) -> AppResult<()> {
+        // given
+        assert_eq!(table_count(&pool, "messages").await?, 5);
+        assert_eq!(get_system_prompt(&pool, 2).await?, None);
+
+        // when
+        let system_prompt = create_system_prompt(&pool, "You are a comedian", 2).await?;
+
+        // then
+        assert_eq!(table_count(&pool, "messages").await?, 6);
+        assert_eq!(system_prompt.role, Role::System);
+        assert_eq!(system_prompt.content, "You are a comedian");
+        assert_eq!(
+            system_prompt.created_at,
+            get_conversation(&pool, 2).await?.created_at
+        );
+        assert_eq!(
+            get_system_prompt(&pool, 2).await?,
+            Some(system_prompt.clone())
+        );
+        assert_eq!(get_messages(&pool, 2).await?[0], system_prompt);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql"))]
+    async fn test_create_system_prompt_conversation_doesnt_exist(
+        pool: SqlitePool,
+    ) -> AppResult<()> {
+        // when
+        let result = create_system_prompt(&pool, "You are a comedian", 9999).await;
+
+        // then
+        assert!(result.is_err());
+        assert_eq!(table_count(&pool, "messages").await?, 0);
+
+        Ok(())
+    }
+
     #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
     async fn test_update_message_that_doesnt_exist(pool: SqlitePool) -> AppResult<()> {
         // given
@@ -0,0 +1,273 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use sqlx::SqlitePool;
+
+use crate::{
+    backend::InferenceBackend,
+    db,
+    models::{Conversation, Message},
+    AppResult,
+};
+
+/// Mismatches between the database and session files in chats directory.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// Session files that don't belong to any conversation
+    pub orphaned_files: Vec<PathBuf>,
+    /// Conversations with messages whose session file doesn't exist, llama backend rebuilds
+    /// them when conversation is continued
+    pub missing_sessions: Vec<Conversation>,
+    /// Messages of conversations that no longer exist
+    pub dangling_messages: Vec<Message>,
+    /// Conversations whose active branch points at a message that doesn't exist
+    pub broken_branches: Vec<Conversation>,
+}
+
+impl Report {
+    /// Problems that are worth fixing, missing session files are recreated on their own.
+    pub fn problems(&self) -> usize {
+        self.orphaned_files.len() + self.dangling_messages.len() + self.broken_branches.len()
+    }
+}
+
+/// Reconciles session paths stored in the database with files in chats directory,
+/// nothing is changed.
+pub async fn check(sqlite: &SqlitePool, chats_dir: &Path) -> AppResult<Report> {
+    let referenced = db::get_all_conversations(sqlite)
+        .await?
+        .into_iter()
+        .map(|conversation| PathBuf::from(conversation.session_path))
+        .collect::<HashSet<_>>();
+
+    let mut orphaned_files = vec![];
+    if chats_dir.exists() {
+        let mut entries = tokio::fs::read_dir(chats_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            // staging directories of restore and other files aren't touched
+            let is_session = entry.file_type().await?.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension == "llama");
+            if is_session && !referenced.contains(&path) {
+                orphaned_files.push(path);
+            }
+        }
+    }
+    orphaned_files.sort();
+
+    let missing_sessions = db::get_conversations_with_messages(sqlite)
+        .await?
+        .into_iter()
+        .filter(|conversation| !Path::new(&conversation.session_path).exists())
+        .collect();
+
+    Ok(Report {
+        orphaned_files,
+        missing_sessions,
+        dangling_messages: db::get_dangling_messages(sqlite).await?,
+        broken_branches: db::get_broken_branches(sqlite).await?,
+    })
+}
+
+/// Returns new directory inside chats directory that session files are moved aside to.
+pub fn quarantine_dir(chats_dir: &Path) -> PathBuf {
+    chats_dir.join(format!(
+        ".orphaned-{}",
+        Utc::now().format("%Y%m%d%H%M%S%3f")
+    ))
+}
+
+/// Moves session files into given directory instead of removing them, chats directory is shared
+/// by all databases, so files that look orphaned may belong to a database other than the checked one.
+pub async fn quarantine(paths: &[PathBuf], dir: &Path) -> AppResult<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    tokio::fs::create_dir_all(dir).await?;
+    for path in paths {
+        let Some(file_name) = path.file_name() else {
+            continue;
+        };
+        match tokio::fs::rename(path, dir.join(file_name)).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves orphaned session files aside, removes dangling messages, repairs broken branches and
+/// rebuilds missing sessions. Returns directory orphaned files were moved to.
+pub async fn fix(
+    sqlite: &SqlitePool,
+    report: &Report,
+    chats_dir: &Path,
+    inference_backend: &dyn InferenceBackend,
+) -> AppResult<Option<PathBuf>> {
+    let mut tx = sqlite.begin().await?;
+    db::delete_dangling_messages(&mut *tx).await?;
+    for conversation in &report.broken_branches {
+        db::reset_active_branch(&mut *tx, conversation.id).await?;
+    }
+    tx.commit().await?;
+
+    let quarantine_dir =
+        (!report.orphaned_files.is_empty()).then(|| self::quarantine_dir(chats_dir));
+    if let Some(dir) = &quarantine_dir {
+        quarantine(&report.orphaned_files, dir).await?;
+    }
+
+    for conversation in &report.missing_sessions {
+        let history = db::get_messages(sqlite, conversation.id).await?;
+        inference_backend
+            .rebuild_session(conversation.clone(), history)
+            .await?;
+    }
+
+    Ok(quarantine_dir)
+}
+
+/// Cheap check run before the TUI starts, it only warns about problems.
+pub async fn startup_check(sqlite: &SqlitePool, chats_dir: &Path) {
+    match check(sqlite, chats_dir).await {
+        Ok(report) if report.problems() > 0 => tracing::warn!(
+            "found {} orphaned session files, {} dangling messages and {} broken branches, run `lokai doctor --fix` to clean them up",
+            report.orphaned_files.len(),
+            report.dangling_messages.len(),
+            report.broken_branches.len()
+        ),
+        Ok(_) => {}
+        Err(err) => tracing::warn!("cannot check database and session files: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_check_and_fix(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let chats_dir = std::env::temp_dir().join(format!("lokai-doctor-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(chats_dir.join(".restore-1")).await?;
+        let session_path = chats_dir.join("1.llama");
+        let orphaned_path = chats_dir.join("deleted.llama");
+        tokio::fs::write(&session_path, b"session").await?;
+        tokio::fs::write(&orphaned_path, b"session").await?;
+        tokio::fs::write(chats_dir.join("notes.txt"), b"notes").await?;
+        db::update_session_path(&pool, 1, session_path.to_str().unwrap()).await?;
+        // conversation deleted by a tool that doesn't enforce foreign keys
+        let mut conn = pool.acquire().await?;
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(
+            "INSERT INTO messages(id, role, content, conversation_id) VALUES (6, 'user', 'hi', 99)",
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query("PRAGMA foreign_keys = ON")
+            .execute(&mut *conn)
+            .await?;
+        drop(conn);
+        sqlx::query("UPDATE conversations SET active_message_id = 1 WHERE id = 2")
+            .execute(&pool)
+            .await?;
+
+        // when
+        let report = check(&pool, &chats_dir).await?;
+
+        // then
+        assert_eq!(report.orphaned_files, vec![orphaned_path.clone()]);
+        assert_eq!(
+            report
+                .missing_sessions
+                .iter()
+                .map(|conversation| conversation.id)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(
+            report
+                .dangling_messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+            vec![6]
+        );
+        assert_eq!(
+            report
+                .broken_branches
+                .iter()
+                .map(|conversation| conversation.id)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(report.problems(), 3);
+
+        // when
+        let quarantine_dir = fix(&pool, &report, &chats_dir, &MockBackend::echo()).await?;
+
+        // then
+        assert!(!orphaned_path.exists());
+        assert!(quarantine_dir.unwrap().join("deleted.llama").exists());
+        assert!(session_path.exists());
+        assert!(chats_dir.join("notes.txt").exists());
+        let report = check(&pool, &chats_dir).await?;
+        assert_eq!(report.problems(), 0);
+        let messages = db::get_messages(&pool, 2).await?;
+        assert_eq!(messages.last().map(|message| message.id), Some(5));
+
+        tokio::fs::remove_dir_all(chats_dir).await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_fix_keeps_sessions_of_other_database(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let chats_dir = std::env::temp_dir().join(format!("lokai-doctor-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&chats_dir).await?;
+        let session_path = chats_dir.join("1.llama");
+        tokio::fs::write(&session_path, b"session").await?;
+
+        // when
+        // empty database doesn't know any session
+        let report = check(&pool, &chats_dir).await?;
+        let quarantine_dir = fix(&pool, &report, &chats_dir, &MockBackend::echo()).await?;
+
+        // then
+        assert_eq!(report.orphaned_files, vec![session_path.clone()]);
+        let quarantined = quarantine_dir.unwrap().join("1.llama");
+        assert_eq!(tokio::fs::read(quarantined).await?, b"session");
+        // quarantined files aren't reported again
+        assert_eq!(check(&pool, &chats_dir).await?, Report::default());
+
+        tokio::fs::remove_dir_all(chats_dir).await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_check_missing_chats_dir(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let chats_dir = std::env::temp_dir().join(format!("lokai-doctor-{}", uuid::Uuid::new_v4()));
+
+        // when
+        let report = check(&pool, &chats_dir).await?;
+
+        // then
+        assert_eq!(report, Report::default());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use futures::{future::BoxFuture, FutureExt};
+use kalosm::language::{Bert, BertSource, EmbedderExt};
+use kalosm_language::kalosm_llama::Cache;
+use sqlx::SqlitePool;
+
+use crate::{db, models::Conversation, AppResult};
+
+/// Maximum number of related conversations shown to the user
+pub const RELATED_CONVERSATIONS_LIMIT: usize = 10;
+
+/// Turns text into vectors, texts with similar meaning end up close to each other.
+pub trait Embedder: Send + Sync {
+    /// Name of the model, vectors of different models cannot be compared.
+    fn model(&self) -> String;
+
+    fn embed(&self, text: &str) -> BoxFuture<'_, AppResult<Vec<f32>>>;
+}
+
+/// Local BERT model run by kalosm, works offline once the model is downloaded.
+pub struct BertEmbedder {
+    bert: Bert,
+}
+
+impl BertEmbedder {
+    pub async fn new(cache: Cache) -> AppResult<Self> {
+        let bert = Bert::builder()
+            .with_source(BertSource::snowflake_arctic_embed_extra_small().with_cache(cache))
+            .build()
+            .await?;
+
+        Ok(Self { bert })
+    }
+}
+
+impl Embedder for BertEmbedder {
+    fn model(&self) -> String {
+        "snowflake-arctic-embed-xs".to_string()
+    }
+
+    fn embed(&self, text: &str) -> BoxFuture<'_, AppResult<Vec<f32>>> {
+        let text = text.to_string();
+        async move {
+            let embedding = self.bert.embed(text).await?;
+
+            Ok(embedding.to_vec())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelatedConversation {
+    pub conversation: Conversation,
+    /// Cosine similarity of conversations, the higher the more related they are
+    pub similarity: f32,
+}
+
+/// Embeds messages that don't have embedding of embedder's model yet, returns number of embedded messages.
+pub async fn embed_messages(sqlite: &SqlitePool, embedder: &dyn Embedder) -> AppResult<usize> {
+    let model = embedder.model();
+    let messages = db::get_messages_without_embedding(sqlite, &model).await?;
+    for message in &messages {
+        let embedding = embedder.embed(&message.content).await?;
+        db::upsert_embedding(sqlite, message.id, &model, &embedding).await?;
+    }
+
+    Ok(messages.len())
+}
+
+/// Finds conversations about similar topics. Every conversation is represented by the mean
+/// of its messages' embeddings, so only embedded messages are taken into account.
+pub async fn related_conversations(
+    sqlite: &SqlitePool,
+    model: &str,
+    conversation_id: u32,
+    limit: usize,
+) -> AppResult<Vec<RelatedConversation>> {
+    let mut sums: HashMap<u32, Vec<f32>> = HashMap::new();
+    for message_embedding in db::get_embeddings(sqlite, model).await? {
+        let sum = sums
+            .entry(message_embedding.conversation_id)
+            .or_insert_with(|| vec![0.0; message_embedding.embedding.len()]);
+        // mean has the same direction as the sum, it's enough for cosine similarity
+        for (total, value) in sum.iter_mut().zip(&message_embedding.embedding) {
+            *total += value;
+        }
+    }
+    let Some(target) = sums.remove(&conversation_id) else {
+        return Ok(vec![]);
+    };
+
+    let mut related = vec![];
+    for conversation in db::get_conversations(sqlite).await? {
+        if let Some(sum) = sums.get(&conversation.id) {
+            let similarity = cosine_similarity(&target, sum);
+            related.push(RelatedConversation {
+                conversation,
+                similarity,
+            });
+        }
+    }
+    related.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    related.truncate(limit);
+
+    Ok(related)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm = |v: &[f32]| v.iter().map(|value| value * value).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        return 0.0;
+    }
+
+    dot / norms
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::models::Role;
+
+    /// Counts words of a tiny vocabulary, so texts about the same topic point the same direction.
+    struct WordsEmbedder;
+
+    impl Embedder for WordsEmbedder {
+        fn model(&self) -> String {
+            "words".to_string()
+        }
+
+        fn embed(&self, text: &str) -> BoxFuture<'_, AppResult<Vec<f32>>> {
+            let text = text.to_lowercase();
+            async move {
+                Ok(["sky", "blue", "joke", "capital"]
+                    .iter()
+                    .map(|word| text.matches(word).count() as f32)
+                    .collect())
+            }
+            .boxed()
+        }
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_related_conversations(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let embedder = WordsEmbedder;
+        let message = db::create_message(&pool, Role::User, "blue sky", 3).await?;
+
+        // when
+        let embedded = embed_messages(&pool, &embedder).await?;
+
+        // then
+        assert_eq!(embedded, 6);
+        assert_eq!(embed_messages(&pool, &embedder).await?, 0);
+
+        let related = related_conversations(&pool, "words", 1, 10).await?;
+        let ids = related
+            .iter()
+            .map(|related| related.conversation.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 2]);
+        assert!((related[0].similarity - 1.0).abs() < 1e-6);
+        assert_eq!(related[1].similarity, 0.0);
+        assert_eq!(related_conversations(&pool, "words", 1, 1).await?.len(), 1);
+        // conversations without embeddings aren't related to anything
+        assert!(related_conversations(&pool, "words", 4, 10)
+            .await?
+            .is_empty());
+        assert!(related_conversations(&pool, "bert", 1, 10)
+            .await?
+            .is_empty());
+
+        db::update_message(&pool, "tell me a joke", message.id).await?;
+        assert_eq!(embed_messages(&pool, &embedder).await?, 1);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(&[1.0, 0.0], &[2.0, 0.0], 1.0)]
+    #[case(&[1.0, 0.0], &[0.0, 1.0], 0.0)]
+    #[case(&[1.0, 1.0], &[-1.0, -1.0], -1.0)]
+    #[case(&[0.0, 0.0], &[1.0, 1.0], 0.0)]
+    fn test_cosine_similarity(#[case] a: &[f32], #[case] b: &[f32], #[case] expected: f32) {
+        assert!((cosine_similarity(a, b) - expected).abs() < 1e-6);
+    }
+}
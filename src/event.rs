@@ -8,7 +8,10 @@ use tokio::{
     time::interval,
 };
 
-use crate::{models::Message, AppResult};
+use crate::{
+    models::{Conversation, Message},
+    AppResult,
+};
 
 #[derive(Clone, Debug)]
 pub enum InferenceType {
@@ -21,6 +24,10 @@ pub enum Event {
     TerminalTick,
     Key(KeyEvent),
     Inference(Message, InferenceType),
+    /// Response couldn't be generated, message is either the failed response or the prompt
+    /// when response wasn't created at all
+    InferenceFailed(Message, String),
+    ConversationRenamed(Conversation),
     ChatBottomScroll,
     PromptTranscription(String),
 }
@@ -0,0 +1,260 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    db,
+    models::{display_name, Conversation, Message, Role},
+    AppResult,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    /// Markdown with a heading per message
+    #[default]
+    Markdown,
+    /// Lossless JSON document, it can be imported back
+    Json,
+    /// Standalone HTML page
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+
+    pub fn next(&self) -> ExportFormat {
+        match self {
+            ExportFormat::Markdown => ExportFormat::Json,
+            ExportFormat::Json => ExportFormat::Html,
+            ExportFormat::Html => ExportFormat::Markdown,
+        }
+    }
+}
+
+impl core::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Markdown => write!(f, "markdown"),
+            ExportFormat::Json => write!(f, "json"),
+            ExportFormat::Html => write!(f, "html"),
+        }
+    }
+}
+
+/// JSON document with a conversation and messages of its current branch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConversationExport {
+    pub conversation: Conversation,
+    pub messages: Vec<Message>,
+}
+
+/// Writes conversation into given directory, returns path of the created file.
+pub async fn export_conversation(
+    sqlite: &SqlitePool,
+    conversation_id: u32,
+    format: ExportFormat,
+    dir: &Path,
+) -> AppResult<PathBuf> {
+    let conversation = db::get_conversation(sqlite, conversation_id).await?;
+    let messages = db::get_messages(sqlite, conversation_id).await?;
+    let content = render(format, conversation.clone(), messages)?;
+
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(format!(
+        "{}.{}",
+        file_stem(&conversation),
+        format.extension()
+    ));
+    tokio::fs::write(&path, content).await?;
+
+    Ok(path)
+}
+
+pub fn render(
+    format: ExportFormat,
+    conversation: Conversation,
+    messages: Vec<Message>,
+) -> AppResult<String> {
+    let content = match format {
+        ExportFormat::Markdown => to_markdown(&conversation, &messages),
+        ExportFormat::Json => serde_json::to_string_pretty(&ConversationExport {
+            conversation,
+            messages,
+        })?,
+        ExportFormat::Html => to_html(&conversation, &messages),
+    };
+
+    Ok(content)
+}
+
+fn title(conversation: &Conversation) -> &str {
+    display_name(&conversation.name)
+}
+
+fn role_heading(role: &Role) -> &'static str {
+    match role {
+        Role::Assistant => "Assistant",
+        Role::System => "System",
+        Role::User => "User",
+    }
+}
+
+fn to_markdown(conversation: &Conversation, messages: &[Message]) -> String {
+    let mut markdown = format!(
+        "# {}\n\n_Created at {}_\n",
+        title(conversation),
+        conversation.created_at.format("%Y-%m-%d %H:%M")
+    );
+    for message in messages {
+        markdown.push_str(&format!(
+            "\n## {}\n\n{}\n",
+            role_heading(&message.role),
+            message.content.trim()
+        ));
+        if message.interrupted {
+            markdown.push_str("\n_Response was interrupted_\n");
+        }
+    }
+
+    markdown
+}
+
+fn to_html(conversation: &Conversation, messages: &[Message]) -> String {
+    let title = escape_html(title(conversation));
+    let mut html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; }}
+.message {{ margin: 1rem 0; padding: 0.5rem 1rem; border-radius: 0.5rem; background: #f4f4f4; }}
+.message.user {{ background: #e3effd; }}
+.message.system {{ background: #fdf6e3; }}
+.content {{ white-space: pre-wrap; }}
+.meta {{ color: #777; font-size: 0.85rem; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p class="meta">Created at {}</p>
+"#,
+        conversation.created_at.format("%Y-%m-%d %H:%M")
+    );
+    for message in messages {
+        html.push_str(&format!(
+            "<div class=\"message {}\">\n<h2>{}</h2>\n<div class=\"content\">{}</div>\n",
+            message.role,
+            role_heading(&message.role),
+            escape_html(message.content.trim())
+        ));
+        if message.interrupted {
+            html.push_str("<p class=\"meta\">Response was interrupted</p>\n");
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// File name made of conversation's id and name, so exports of different conversations don't collide.
+fn file_stem(conversation: &Conversation) -> String {
+    let name = conversation
+        .name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    match name.as_str() {
+        "" => format!("conversation-{}", conversation.id),
+        name => format!("conversation-{}-{}", conversation.id, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_export_conversation(pool: SqlitePool) -> AppResult<()> {
+        // given
+        let dir = std::env::temp_dir().join(format!("lokai-export-{}", uuid::Uuid::new_v4()));
+
+        // when
+        let markdown = export_conversation(&pool, 1, ExportFormat::Markdown, &dir).await?;
+        let json = export_conversation(&pool, 1, ExportFormat::Json, &dir).await?;
+        let html = export_conversation(&pool, 1, ExportFormat::Html, &dir).await?;
+
+        // then
+        assert_eq!(markdown, dir.join("conversation-1-conversation-1.md"));
+        assert_eq!(
+            std::fs::read_to_string(&markdown)?,
+            "# conversation 1\n\n_Created at 2024-09-13 09:00_\n\n## User\n\nwhy is the sky blue?\n\n## Assistant\n\nI don't know\n"
+        );
+
+        let export: ConversationExport = serde_json::from_str(&std::fs::read_to_string(&json)?)?;
+        assert_eq!(export.conversation, db::get_conversation(&pool, 1).await?);
+        assert_eq!(export.messages, db::get_messages(&pool, 1).await?);
+
+        let html = std::fs::read_to_string(&html)?;
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<div class=\"content\">I don&#39;t know</div>"));
+
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_html_escapes_content() {
+        // given
+        let conversation = Conversation::new_test(1, "<b>tags</b>");
+        let message = Message {
+            interrupted: true,
+            ..Message::new_test(1, Role::User, "what does <script> do?")
+        };
+
+        // when
+        let html = to_html(&conversation, &[message]);
+
+        // then
+        assert!(html.contains("<title>&lt;b&gt;tags&lt;/b&gt;</title>"));
+        assert!(html.contains("what does &lt;script&gt; do?"));
+        assert!(html.contains("Response was interrupted"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[rstest]
+    #[case(1, "Why is the sky blue?", "conversation-1-why-is-the-sky-blue")]
+    #[case(2, "  Żółw / C++  ", "conversation-2-żółw-c")]
+    #[case(3, "", "conversation-3")]
+    #[case(4, "???", "conversation-4")]
+    fn test_file_stem(#[case] id: u32, #[case] name: &str, #[case] expected: &str) {
+        assert_eq!(file_stem(&Conversation::new_test(id, name)), expected);
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+use crate::{
+    db,
+    export::ConversationExport,
+    models::{GenerationSettings, Role},
+    AppResult,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ImportFormat {
+    /// `conversations.json` from ChatGPT's data export
+    Chatgpt,
+    /// JSON export of Open WebUI chats
+    OpenWebui,
+    /// JSON export of LokAI conversation
+    Lokai,
+}
+
+/// Conversation parsed from another tool, only messages of its current branch are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedConversation {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub settings: GenerationSettings,
+    pub messages: Vec<ImportedMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedMessage {
+    pub role: Role,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub interrupted: bool,
+}
+
+/// Outcome of an import, names of conversations with numbers of their messages.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    pub imported: Vec<(String, usize)>,
+    /// Conversations with the same name and creation time that already exist
+    pub skipped: Vec<String>,
+}
+
+/// Parses export file of another tool, format is detected when it's not given.
+pub fn parse(content: &str, format: Option<ImportFormat>) -> AppResult<Vec<ImportedConversation>> {
+    let value: Value = serde_json::from_str(content)?;
+    let Some(format) = format.or_else(|| detect_format(&value)) else {
+        return Err("Cannot detect format of the file, set it explicitly".into());
+    };
+    // every tool exports either a single conversation or a list of them
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    let mut conversations = vec![];
+    for value in values {
+        let conversation = match format {
+            ImportFormat::Chatgpt => parse_chatgpt(serde_json::from_value(value)?),
+            ImportFormat::OpenWebui => parse_open_webui(serde_json::from_value(value)?),
+            ImportFormat::Lokai => parse_lokai(serde_json::from_value(value)?),
+        };
+        conversations.push(conversation);
+    }
+
+    Ok(conversations)
+}
+
+fn detect_format(value: &Value) -> Option<ImportFormat> {
+    let value = match value {
+        Value::Array(values) => values.first()?,
+        value => value,
+    };
+    if value.get("mapping").is_some() {
+        Some(ImportFormat::Chatgpt)
+    } else if value.get("chat").is_some() {
+        Some(ImportFormat::OpenWebui)
+    } else if value.get("conversation").is_some() && value.get("messages").is_some() {
+        Some(ImportFormat::Lokai)
+    } else {
+        None
+    }
+}
+
+/// Stores conversations inside a single transaction, nothing is stored when any of them fails.
+/// Dry run rolls the transaction back, so it reports exactly what would be imported.
+pub async fn import_conversations<F>(
+    sqlite: &SqlitePool,
+    conversations: Vec<ImportedConversation>,
+    new_session_path: F,
+    dry_run: bool,
+) -> AppResult<ImportReport>
+where
+    F: Fn() -> String,
+{
+    let mut report = ImportReport::default();
+    let mut transaction = sqlite.begin().await?;
+    for conversation in conversations {
+        if db::find_conversation(
+            &mut *transaction,
+            &conversation.name,
+            conversation.created_at,
+        )
+        .await?
+        .is_some()
+        {
+            report.skipped.push(conversation.name);
+            continue;
+        }
+
+        let new_conversation = db::import_conversation(
+            &mut *transaction,
+            &conversation.name,
+            &new_session_path(),
+            conversation.created_at,
+            &conversation.settings,
+        )
+        .await?;
+        let mut parent_id = None;
+        for message in &conversation.messages {
+            let new_message = db::import_message(
+                &mut *transaction,
+                message.role.clone(),
+                &message.content,
+                new_conversation.id,
+                // system prompt isn't a part of the path
+                parent_id.filter(|_| message.role != Role::System),
+                message.created_at,
+            )
+            .await?;
+            if message.interrupted {
+                db::interrupt_message(&mut *transaction, &message.content, new_message.id).await?;
+            }
+            if message.role != Role::System {
+                parent_id = Some(new_message.id);
+            }
+        }
+        report
+            .imported
+            .push((conversation.name, conversation.messages.len()));
+    }
+
+    match dry_run {
+        true => transaction.rollback().await?,
+        false => transaction.commit().await?,
+    }
+
+    Ok(report)
+}
+
+fn parse_role(role: &str) -> Option<Role> {
+    match role {
+        "assistant" => Some(Role::Assistant),
+        "system" => Some(Role::System),
+        "user" => Some(Role::User),
+        // tool calls and their outputs don't have LokAI's counterpart
+        _ => None,
+    }
+}
+
+fn timestamp_secs(secs: f64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis((secs * 1000.0) as i64)
+}
+
+/// Fills missing timestamps with the previous ones, so messages keep their order.
+fn with_timestamps(
+    created_at: DateTime<Utc>,
+    messages: Vec<(Role, String, Option<DateTime<Utc>>)>,
+) -> Vec<ImportedMessage> {
+    let mut last_created_at = created_at;
+    messages
+        .into_iter()
+        .filter(|(_, content, _)| !content.trim().is_empty())
+        .map(|(role, content, message_created_at)| {
+            last_created_at = message_created_at.unwrap_or(last_created_at);
+            ImportedMessage {
+                role,
+                content,
+                created_at: last_created_at,
+                interrupted: false,
+            }
+        })
+        .collect()
+}
+
+/// Follows parents from the leaf to the root, returns ids ordered from the root.
+fn path_to<T>(
+    nodes: &HashMap<String, T>,
+    leaf: Option<String>,
+    parent: impl Fn(&T) -> Option<String>,
+) -> Vec<String> {
+    let mut path = vec![];
+    let mut current = leaf;
+    while let Some(id) = current {
+        // malformed file could make a cycle
+        if path.len() > nodes.len() {
+            break;
+        }
+        let Some(node) = nodes.get(&id) else {
+            break;
+        };
+        current = parent(node);
+        path.push(id);
+    }
+    path.reverse();
+
+    path
+}
+
+#[derive(Deserialize)]
+struct ChatGptConversation {
+    title: Option<String>,
+    create_time: Option<f64>,
+    #[serde(default)]
+    mapping: HashMap<String, ChatGptNode>,
+    current_node: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptNode {
+    message: Option<ChatGptMessage>,
+    parent: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptMessage {
+    author: ChatGptAuthor,
+    create_time: Option<f64>,
+    content: ChatGptContent,
+}
+
+#[derive(Deserialize)]
+struct ChatGptAuthor {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct ChatGptContent {
+    /// Text parts, other parts (e.g. images) are skipped
+    #[serde(default)]
+    parts: Vec<Value>,
+}
+
+fn parse_chatgpt(conversation: ChatGptConversation) -> ImportedConversation {
+    let created_at = conversation
+        .create_time
+        .and_then(timestamp_secs)
+        .unwrap_or_else(Utc::now);
+    // the most recent message is the leaf of the current branch when it's not known
+    let leaf = conversation.current_node.or_else(|| {
+        conversation
+            .mapping
+            .iter()
+            .filter_map(|(id, node)| Some((id, node.message.as_ref()?.create_time?)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(id, _)| id.clone())
+    });
+    let messages = path_to(&conversation.mapping, leaf, |node| node.parent.clone())
+        .iter()
+        .filter_map(|id| {
+            let message = conversation.mapping.get(id)?.message.as_ref()?;
+            let role = parse_role(&message.author.role)?;
+            let content = message
+                .content
+                .parts
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("\n");
+            Some((role, content, message.create_time.and_then(timestamp_secs)))
+        })
+        .collect();
+
+    ImportedConversation {
+        name: conversation.title.unwrap_or_default(),
+        created_at,
+        settings: Default::default(),
+        messages: with_timestamps(created_at, messages),
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenWebUiChat {
+    title: Option<String>,
+    /// Seconds since epoch
+    created_at: Option<i64>,
+    chat: OpenWebUiChatContent,
+}
+
+#[derive(Deserialize)]
+struct OpenWebUiChatContent {
+    title: Option<String>,
+    /// Milliseconds since epoch
+    timestamp: Option<i64>,
+    #[serde(default)]
+    messages: Vec<OpenWebUiMessage>,
+    history: Option<OpenWebUiHistory>,
+}
+
+#[derive(Deserialize)]
+struct OpenWebUiHistory {
+    #[serde(default)]
+    messages: HashMap<String, OpenWebUiMessage>,
+    #[serde(rename = "currentId")]
+    current_id: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct OpenWebUiMessage {
+    #[serde(rename = "parentId")]
+    parent_id: Option<String>,
+    role: String,
+    #[serde(default)]
+    content: String,
+    /// Seconds since epoch
+    timestamp: Option<i64>,
+}
+
+fn parse_open_webui(chat: OpenWebUiChat) -> ImportedConversation {
+    let created_at = chat
+        .created_at
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .or_else(|| {
+            chat.chat
+                .timestamp
+                .and_then(DateTime::from_timestamp_millis)
+        })
+        .unwrap_or_else(Utc::now);
+    // history keeps all branches, the flat list only the current one
+    let messages = match chat.chat.history {
+        Some(history) if !history.messages.is_empty() => {
+            path_to(&history.messages, history.current_id, |message| {
+                message.parent_id.clone()
+            })
+            .iter()
+            .filter_map(|id| history.messages.get(id).cloned())
+            .collect()
+        }
+        _ => chat.chat.messages,
+    };
+    let messages = messages
+        .into_iter()
+        .filter_map(|message| {
+            let role = parse_role(&message.role)?;
+            let message_created_at = message
+                .timestamp
+                .and_then(|secs| DateTime::from_timestamp(secs, 0));
+            Some((role, message.content, message_created_at))
+        })
+        .collect();
+
+    ImportedConversation {
+        name: chat.title.or(chat.chat.title).unwrap_or_default(),
+        created_at,
+        settings: Default::default(),
+        messages: with_timestamps(created_at, messages),
+    }
+}
+
+fn parse_lokai(export: ConversationExport) -> ImportedConversation {
+    ImportedConversation {
+        name: export.conversation.name,
+        created_at: export.conversation.created_at,
+        settings: export.conversation.settings,
+        messages: export
+            .messages
+            .into_iter()
+            .filter(|message| !message.summary)
+            .map(|message| ImportedMessage {
+                role: message.role,
+                content: message.content,
+                created_at: message.created_at,
+                interrupted: message.interrupted,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{self, ExportFormat};
+
+    fn datetime(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    fn imported_message(role: Role, content: &str, created_at: &str) -> ImportedMessage {
+        ImportedMessage {
+            role,
+            content: content.to_string(),
+            created_at: datetime(created_at),
+            interrupted: false,
+        }
+    }
+
+    const CHATGPT: &str = r#"[{
+        "title": "Sky colour",
+        "create_time": 1726218000.5,
+        "current_node": "c",
+        "mapping": {
+            "root": {"id": "root", "message": null, "parent": null, "children": ["s"]},
+            "s": {"id": "s", "parent": "root", "children": ["a"], "message": {
+                "author": {"role": "system"}, "create_time": null,
+                "content": {"content_type": "text", "parts": [""]}
+            }},
+            "a": {"id": "a", "parent": "s", "children": ["b", "b2"], "message": {
+                "author": {"role": "user"}, "create_time": 1726218001.0,
+                "content": {"content_type": "text", "parts": ["why is the sky blue?"]}
+            }},
+            "b2": {"id": "b2", "parent": "a", "children": [], "message": {
+                "author": {"role": "assistant"}, "create_time": 1726218002.0,
+                "content": {"content_type": "text", "parts": ["discarded answer"]}
+            }},
+            "b": {"id": "b", "parent": "a", "children": ["c"], "message": {
+                "author": {"role": "tool"}, "create_time": 1726218003.0,
+                "content": {"content_type": "text", "parts": ["search results"]}
+            }},
+            "c": {"id": "c", "parent": "b", "children": [], "message": {
+                "author": {"role": "assistant"}, "create_time": null,
+                "content": {"content_type": "text", "parts": ["Rayleigh", {"asset": "image"}, "scattering"]}
+            }}
+        }
+    }]"#;
+
+    const OPEN_WEBUI: &str = r#"[{
+        "id": "1",
+        "title": "Jokes",
+        "created_at": 1726218060,
+        "chat": {
+            "title": "Jokes",
+            "messages": [],
+            "history": {
+                "currentId": "m3",
+                "messages": {
+                    "m1": {"id": "m1", "parentId": null, "role": "user", "content": "tell me a joke", "timestamp": 1726218061},
+                    "m2": {"id": "m2", "parentId": "m1", "role": "assistant", "content": "old joke", "timestamp": 1726218062},
+                    "m3": {"id": "m3", "parentId": "m1", "role": "assistant", "content": "I don't know any jokes", "timestamp": 1726218063}
+                }
+            }
+        }
+    }, {
+        "title": "Capitals",
+        "chat": {
+            "timestamp": 1726218120000,
+            "messages": [
+                {"role": "user", "content": "what's the capital of Poland?", "timestamp": 1726218121},
+                {"role": "assistant", "content": "Warsaw"}
+            ]
+        }
+    }]"#;
+
+    #[test]
+    fn test_parse_chatgpt() -> AppResult<()> {
+        // when
+        let conversations = parse(CHATGPT, None)?;
+
+        // then
+        assert_eq!(
+            conversations,
+            vec![ImportedConversation {
+                name: "Sky colour".to_string(),
+                created_at: datetime("2024-09-13T09:00:00.500Z"),
+                settings: Default::default(),
+                messages: vec![
+                    imported_message(Role::User, "why is the sky blue?", "2024-09-13T09:00:01Z"),
+                    imported_message(
+                        Role::Assistant,
+                        "Rayleigh\nscattering",
+                        "2024-09-13T09:00:01Z"
+                    ),
+                ],
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_open_webui() -> AppResult<()> {
+        // when
+        let conversations = parse(OPEN_WEBUI, None)?;
+
+        // then
+        assert_eq!(
+            conversations,
+            vec![
+                ImportedConversation {
+                    name: "Jokes".to_string(),
+                    created_at: datetime("2024-09-13T09:01:00Z"),
+                    settings: Default::default(),
+                    messages: vec![
+                        imported_message(Role::User, "tell me a joke", "2024-09-13T09:01:01Z"),
+                        imported_message(
+                            Role::Assistant,
+                            "I don't know any jokes",
+                            "2024-09-13T09:01:03Z"
+                        ),
+                    ],
+                },
+                ImportedConversation {
+                    name: "Capitals".to_string(),
+                    created_at: datetime("2024-09-13T09:02:00Z"),
+                    settings: Default::default(),
+                    messages: vec![
+                        imported_message(
+                            Role::User,
+                            "what's the capital of Poland?",
+                            "2024-09-13T09:02:01Z"
+                        ),
+                        imported_message(Role::Assistant, "Warsaw", "2024-09-13T09:02:01Z"),
+                    ],
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_unknown_format() {
+        assert!(parse(r#"{"messages": []}"#, None).is_err());
+        assert!(parse("not json", None).is_err());
+        assert!(parse(r#"[{"mapping": {}}]"#, Some(ImportFormat::OpenWebui)).is_err());
+    }
+
+    #[sqlx::test(fixtures("../fixtures/conversations.sql", "../fixtures/messages.sql"))]
+    async fn test_import_lokai_export(pool: SqlitePool) -> AppResult<()> {
+        // given
+        db::create_system_prompt(&pool, "Be fun", 2).await?;
+        db::interrupt_message(&pool, "I don't know any", 4).await?;
+        db::update_conversation_settings(
+            &pool,
+            2,
+            &GenerationSettings {
+                temperature: Some(0.5),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let conversation = db::get_conversation(&pool, 2).await?;
+        let messages = db::get_messages(&pool, 2).await?;
+        let json = export::render(ExportFormat::Json, conversation.clone(), messages.clone())?;
+        db::delete_conversation(&pool, 2).await?;
+        let session_path = || "~/.lokai/chats/imported".to_string();
+
+        // when
+        let dry_run = import_conversations(&pool, parse(&json, None)?, session_path, true).await?;
+
+        // then
+        let report = ImportReport {
+            imported: vec![("conversation 2".to_string(), 4)],
+            skipped: vec![],
+        };
+        assert_eq!(dry_run, report);
+        assert_eq!(db::get_conversations(&pool).await?.len(), 3);
+
+        assert_eq!(
+            import_conversations(&pool, parse(&json, None)?, session_path, false).await?,
+            report
+        );
+        let conversations = db::get_conversations(&pool).await?;
+        let imported = conversations
+            .iter()
+            .find(|c| c.name == "conversation 2")
+            .unwrap();
+        assert_eq!(imported.created_at, conversation.created_at);
+        assert_eq!(imported.settings, conversation.settings);
+        assert_eq!(imported.session_path, "~/.lokai/chats/imported");
+        let imported_messages = db::get_messages(&pool, imported.id).await?;
+        let without_ids = |messages: Vec<crate::models::Message>| {
+            messages
+                .into_iter()
+                .map(|m| (m.role, m.content, m.created_at, m.interrupted))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(without_ids(imported_messages), without_ids(messages));
+
+        // the same conversation isn't imported twice
+        assert_eq!(
+            import_conversations(&pool, parse(&json, None)?, session_path, false).await?,
+            ImportReport {
+                imported: vec![],
+                skipped: vec!["conversation 2".to_string()],
+            }
+        );
+
+        Ok(())
+    }
+}
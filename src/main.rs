@@ -3,11 +3,12 @@ use std::{
     io,
     result::Result,
     sync::{Arc, LazyLock},
+    time::Duration,
 };
 
 use clap::Parser;
-use config::Config;
-use kalosm::language::{Llama, LlamaSource};
+use config::{read_inference_config, Config, InferenceConfig};
+use kalosm::language::Llama;
 use kalosm_language::kalosm_llama::Cache;
 use kalosm_sound::{Whisper, WhisperLanguage, WhisperSource};
 use ratatui::{backend::CrosstermBackend, Terminal};
@@ -17,17 +18,37 @@ use tracing::{info, Level};
 use tracing_subscriber::EnvFilter;
 use transcribe::transcribe;
 
-use crate::{app::App, event::EventHandler, tui::Tui};
+use crate::{
+    app::App,
+    backend::{
+        llama, llama::LlamaBackend, mock, mock::MockBackend, ollama, ollama::OllamaBackend, openai,
+        openai::OpenAIBackend, session_cache, session_cache::SessionCache, BackendKind,
+        InferenceBackend,
+    },
+    commands::Command,
+    embeddings::BertEmbedder,
+    event::EventHandler,
+    tui::Tui,
+};
 
 pub mod app;
 pub mod assistant;
+pub mod backend;
+pub mod backup;
 pub mod chat;
+pub mod commands;
 pub mod config;
+pub mod context;
 pub mod conversations;
 pub mod db;
+pub mod doctor;
+pub mod embeddings;
 pub mod event;
+pub mod export;
+pub mod import;
 pub mod models;
 pub mod prompt;
+pub mod search;
 pub mod transcribe;
 pub mod tui;
 pub mod ui;
@@ -42,6 +63,16 @@ pub struct CliArgs {
     /// Enables prompt transcription
     #[arg(long, action = clap::ArgAction::SetTrue)]
     enable_transcription: bool,
+    /// Enables looking up related conversations with local embedding model
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    enable_semantic_search: bool,
+    /// Days after which deleted conversations are purged from trash [default: 30]
+    #[arg(long)]
+    trash_retention_days: Option<u32>,
+    #[command(flatten)]
+    inference: InferenceConfig,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
 static CONFIG: LazyLock<Arc<RwLock<Config>>> =
@@ -65,10 +96,16 @@ async fn main() -> AppResult<()> {
 
     let cli_args = CliArgs::parse();
     {
+        let mut config = CONFIG.write().await;
+        let file_inference = read_inference_config(&config.config_path())?;
+        config.update_inference(file_inference);
         if let Some(database_url) = &cli_args.database_url {
-            let mut config = CONFIG.write().await;
             config.update_database_url(database_url.clone());
         }
+        config.update_inference(cli_args.inference.clone());
+        if let Some(trash_retention_days) = cli_args.trash_retention_days {
+            config.update_trash_retention_days(trash_retention_days);
+        }
     }
 
     let sqlite = {
@@ -83,6 +120,17 @@ async fn main() -> AppResult<()> {
         Cache::new(kalosm_cache_dir)
     };
 
+    if let Some(command) = cli_args.command {
+        command.run(sqlite, kalosm_cache).await?;
+
+        return Ok(());
+    }
+
+    let inference_backend = {
+        let inference_config = CONFIG.read().await.inference().clone();
+        setup_inference_backend(inference_config, kalosm_cache.clone()).await?
+    };
+
     if cli_args.enable_transcription {
         let whisper = Whisper::builder()
             .with_cache(kalosm_cache.clone())
@@ -94,12 +142,22 @@ async fn main() -> AppResult<()> {
         transcribe(event_tx.clone(), whisper)
     }
 
-    let llama = Llama::builder()
-        .with_source(LlamaSource::llama_3_1_8b_chat().with_cache(kalosm_cache))
-        .build()
-        .await?;
+    {
+        let chats_dir = CONFIG.read().await.chats_dir();
+        doctor::startup_check(&sqlite, &chats_dir).await;
+    }
 
-    let mut app: App = App::new(sqlite, event_tx.clone(), llama);
+    let context_window = CONFIG.read().await.inference().context_window();
+    let mut app: App = App::new(
+        sqlite,
+        event_tx.clone(),
+        inference_backend.clone(),
+        context_window,
+    );
+    if cli_args.enable_semantic_search {
+        let embedder = BertEmbedder::new(kalosm_cache.clone()).await?;
+        app = app.with_embedder(Arc::new(embedder));
+    }
     app.init().await?;
 
     let mut event_handler = EventHandler::new(250, event_tx, event_rx);
@@ -120,9 +178,76 @@ async fn main() -> AppResult<()> {
 
     info!("shutting down!");
 
+    inference_backend.cancel();
+    inference_backend.flush().await?;
+
     Ok(())
 }
 
+async fn setup_inference_backend(
+    inference_config: InferenceConfig,
+    kalosm_cache: Cache,
+) -> AppResult<Arc<dyn InferenceBackend>> {
+    let inference_backend: Arc<dyn InferenceBackend> =
+        match inference_config.backend.unwrap_or(BackendKind::Llama) {
+            BackendKind::Llama => {
+                let model = inference_config
+                    .model
+                    .unwrap_or(llama::DEFAULT_MODEL.to_string());
+                let source = llama::llama_source(&model, inference_config.tokenizer)?;
+                info!("loading model: {}", model);
+                let llama = Llama::builder()
+                    .with_source(source.with_cache(kalosm_cache.clone()))
+                    .build()
+                    .await?;
+                let sessions = SessionCache::shared(
+                    inference_config
+                        .session_cache_size
+                        .unwrap_or(session_cache::DEFAULT_CAPACITY),
+                    Duration::from_secs(
+                        inference_config
+                            .session_idle_timeout
+                            .unwrap_or(session_cache::DEFAULT_IDLE_TIMEOUT_SECS),
+                    ),
+                );
+
+                Arc::new(LlamaBackend::new(llama, model, kalosm_cache, sessions))
+            }
+            BackendKind::Mock => {
+                let mock = match inference_config.mock_replies {
+                    Some(path) => MockBackend::from_file(path)?,
+                    None => MockBackend::echo(),
+                };
+                let token_delay = inference_config
+                    .mock_token_delay
+                    .unwrap_or(mock::DEFAULT_TOKEN_DELAY_MS);
+
+                Arc::new(mock.with_token_delay(Duration::from_millis(token_delay)))
+            }
+            BackendKind::Ollama => Arc::new(OllamaBackend::new(
+                inference_config
+                    .base_url
+                    .unwrap_or(ollama::DEFAULT_BASE_URL.to_string()),
+                inference_config
+                    .model
+                    .unwrap_or(ollama::DEFAULT_MODEL.to_string()),
+            )),
+            BackendKind::Openai => Arc::new(
+                OpenAIBackend::new(
+                    inference_config
+                        .base_url
+                        .unwrap_or(openai::DEFAULT_BASE_URL.to_string()),
+                    inference_config
+                        .model
+                        .unwrap_or(openai::DEFAULT_MODEL.to_string()),
+                )
+                .with_api_key(inference_config.api_key),
+            ),
+        };
+
+    Ok(inference_backend)
+}
+
 async fn setup_sqlite_pool(database_url: &str) -> AppResult<SqlitePool> {
     if !sqlx::Sqlite::database_exists(database_url).await? {
         sqlx::Sqlite::create_database(database_url).await?;
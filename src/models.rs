@@ -2,12 +2,87 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{sqlite::SqliteRow, FromRow, Row};
 
+/// Generation settings of a conversation, backend's defaults are used for unset values.
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone, Default, PartialEq)]
+pub struct GenerationSettings {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub seed: Option<u32>,
+}
+
+/// Shown instead of the name of conversation that wasn't named yet.
+pub const UNNAMED_CONVERSATION: &str = "New conversation";
+
+/// Name of conversation as it's displayed, the name is generated once the first response is ready.
+pub fn display_name(name: &str) -> &str {
+    match name.trim() {
+        "" => UNNAMED_CONVERSATION,
+        name => name,
+    }
+}
+
 #[derive(Serialize, Deserialize, FromRow, Debug, Clone, PartialEq)]
 pub struct Conversation {
     pub id: u32,
     pub name: String,
     pub session_path: String,
     pub created_at: DateTime<Utc>,
+    /// Set when conversation is moved to trash
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[sqlx(flatten)]
+    pub settings: GenerationSettings,
+}
+
+/// Position of a message among alternative versions of the same turn.
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub message_id: u32,
+    /// 1-based position, versions are ordered from the oldest
+    pub position: u32,
+    pub count: u32,
+}
+
+/// Message that matches full-text search query.
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub message_id: u32,
+    pub conversation_id: u32,
+    pub conversation_name: String,
+    /// Fragment of message's content, matching terms are wrapped in square brackets
+    pub snippet: String,
+}
+
+/// Vector representation of message's content, used to find semantically similar conversations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageEmbedding {
+    pub message_id: u32,
+    pub conversation_id: u32,
+    pub embedding: Vec<f32>,
+}
+
+impl FromRow<'_, SqliteRow> for MessageEmbedding {
+    fn from_row(row: &'_ SqliteRow) -> sqlx::Result<Self> {
+        let bytes: Vec<u8> = row.try_get("embedding")?;
+        if bytes.len() % 4 != 0 {
+            return Err(sqlx::Error::ColumnDecode {
+                index: "embedding".to_string(),
+                source: format!("Expected a multiple of 4 bytes, got [{}]", bytes.len()).into(),
+            });
+        }
+        let embedding = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+
+        Ok(MessageEmbedding {
+            message_id: row.try_get("message_id")?,
+            conversation_id: row.try_get("conversation_id")?,
+            embedding,
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -36,7 +111,15 @@ pub struct Message {
     pub role: Role,
     pub content: String,
     pub conversation_id: u32,
+    /// Message this one follows up, it's empty for system prompt and the first message of a conversation
+    pub parent_id: Option<u32>,
     pub created_at: DateTime<Utc>,
+    /// Generation of this message was cancelled before model finished it
+    pub interrupted: bool,
+    /// Number of tokens of the content, it's counted once message is sent to the model
+    pub token_count: Option<u32>,
+    /// System message that summarizes messages up to its parent, it's fed to the model instead of them
+    pub summary: bool,
 }
 
 impl FromRow<'_, SqliteRow> for Message {
@@ -66,7 +149,44 @@ impl FromRow<'_, SqliteRow> for Message {
             role,
             content: row.try_get("content")?,
             conversation_id: row.try_get("conversation_id")?,
+            parent_id: row.try_get("parent_id")?,
             created_at: row.try_get("created_at")?,
+            interrupted: row.try_get("interrupted")?,
+            token_count: row.try_get("token_count")?,
+            summary: row.try_get("summary")?,
         })
     }
 }
+
+#[cfg(test)]
+impl Conversation {
+    /// Conversation that isn't stored in the database.
+    pub fn new_test(id: u32, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            session_path: format!("~/.lokai/chats/{id}"),
+            created_at: Default::default(),
+            deleted_at: None,
+            settings: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Message {
+    /// Message of the first conversation that isn't stored in the database.
+    pub fn new_test(id: u32, role: Role, content: &str) -> Self {
+        Self {
+            id,
+            role,
+            content: content.to_string(),
+            conversation_id: 1,
+            parent_id: None,
+            created_at: Default::default(),
+            interrupted: false,
+            token_count: None,
+            summary: false,
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::ops::Deref;
+
+use crossterm::event::KeyEvent;
+use ratatui::widgets::{List, ListItem, ListState};
+use tui_textarea::TextArea;
+
+use crate::{embeddings::RelatedConversation, models::SearchHit};
+
+/// Maximum number of hits shown in the search popup
+pub const SEARCH_LIMIT: u32 = 50;
+
+#[derive(Default)]
+pub struct SearchPopup {
+    text_area: TextArea<'static>,
+    hits: Vec<SearchHit>,
+    pub state: ListState,
+    activated: bool,
+}
+
+impl Deref for SearchPopup {
+    type Target = TextArea<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.text_area
+    }
+}
+
+impl SearchPopup {
+    pub fn is_activated(&self) -> bool {
+        self.activated
+    }
+
+    pub fn activate(&mut self) {
+        self.activated = true;
+    }
+
+    pub fn deactivate(&mut self) {
+        self.activated = false;
+        self.text_area = Default::default();
+        self.set_hits(vec![]);
+    }
+
+    pub fn handle_input(&mut self, key_event: KeyEvent) {
+        if !self.activated {
+            panic!("Activate popup before you handle input")
+        }
+
+        self.text_area.input(key_event);
+    }
+
+    pub fn text_area_mut(&mut self) -> &mut TextArea<'static> {
+        &mut self.text_area
+    }
+
+    pub fn get_query(&self) -> String {
+        self.text_area.lines().join(" ").trim().to_string()
+    }
+
+    /// Replaces hits of the previous query, the best hit gets selected.
+    pub fn set_hits(&mut self, hits: Vec<SearchHit>) {
+        self.state.select((!hits.is_empty()).then_some(0));
+        self.hits = hits;
+    }
+
+    pub fn selected_hit(&self) -> Option<&SearchHit> {
+        self.hits.get(self.state.selected()?)
+    }
+
+    pub fn up(&mut self) {
+        self.state.scroll_up_by(1);
+    }
+
+    pub fn down(&mut self) {
+        self.state.scroll_down_by(1);
+    }
+
+    pub fn as_list_widget<F, T>(&self, f: F) -> List<'static>
+    where
+        F: Fn(&SearchHit) -> T,
+        T: Into<ListItem<'static>>,
+    {
+        let items = self
+            .hits
+            .iter()
+            .map(|elem| f(elem).into())
+            .collect::<Vec<ListItem>>();
+
+        List::new(items)
+    }
+}
+
+/// Conversations about similar topics as the currently selected one.
+#[derive(Default)]
+pub struct RelatedConversationsPopup {
+    related: Vec<RelatedConversation>,
+    pub state: ListState,
+    activated: bool,
+}
+
+impl RelatedConversationsPopup {
+    pub fn is_activated(&self) -> bool {
+        self.activated
+    }
+
+    /// Shows related conversations, the most similar one gets selected.
+    pub fn activate(&mut self, related: Vec<RelatedConversation>) {
+        self.state.select((!related.is_empty()).then_some(0));
+        self.related = related;
+        self.activated = true;
+    }
+
+    pub fn deactivate(&mut self) {
+        self.activated = false;
+        self.related = vec![];
+        self.state.select(None);
+    }
+
+    pub fn selected(&self) -> Option<&RelatedConversation> {
+        self.related.get(self.state.selected()?)
+    }
+
+    pub fn up(&mut self) {
+        self.state.scroll_up_by(1);
+    }
+
+    pub fn down(&mut self) {
+        self.state.scroll_down_by(1);
+    }
+
+    pub fn as_list_widget<F, T>(&self, f: F) -> List<'static>
+    where
+        F: Fn(&RelatedConversation) -> T,
+        T: Into<ListItem<'static>>,
+    {
+        let items = self
+            .related
+            .iter()
+            .map(|elem| f(elem).into())
+            .collect::<Vec<ListItem>>();
+
+        List::new(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    use super::*;
+    use crate::models::Conversation;
+
+    fn hit(message_id: u32) -> SearchHit {
+        SearchHit {
+            message_id,
+            conversation_id: 1,
+            conversation_name: "conversation 1".to_string(),
+            snippet: "I don't [know]".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_search_popup() {
+        // given
+        let mut popup = SearchPopup::default();
+        popup.activate();
+
+        // when
+        popup.handle_input(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('k'), KeyModifiers::NONE));
+        popup.handle_input(KeyEvent::new(KeyCode::Char('y'), KeyModifiers::NONE));
+        popup.set_hits(vec![hit(1), hit(2)]);
+
+        // then
+        assert_eq!(popup.get_query(), "sky");
+        assert_eq!(popup.selected_hit(), Some(&hit(1)));
+
+        popup.set_hits(vec![]);
+        assert_eq!(popup.selected_hit(), None);
+
+        popup.deactivate();
+        assert!(!popup.is_activated());
+        assert_eq!(popup.get_query(), "");
+    }
+
+    #[test]
+    fn test_related_conversations_popup() {
+        // given
+        let mut popup = RelatedConversationsPopup::default();
+        let related = |id: u32, similarity: f32| RelatedConversation {
+            conversation: Conversation::new_test(id, &format!("conversation {id}")),
+            similarity,
+        };
+
+        // when
+        popup.activate(vec![related(2, 0.9), related(3, 0.5)]);
+
+        // then
+        assert!(popup.is_activated());
+        assert_eq!(popup.selected(), Some(&related(2, 0.9)));
+
+        popup.deactivate();
+        assert!(!popup.is_activated());
+        assert_eq!(popup.selected(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_search_popup_handle_input_not_activated() {
+        // given
+        let mut popup = SearchPopup::default();
+
+        // when
+        popup.handle_input(KeyEvent::new(KeyCode::Char('H'), KeyModifiers::SHIFT));
+    }
+}
@@ -1,6 +1,7 @@
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Style, Stylize},
+    text::Line,
     widgets::{
         Block, BorderType, Borders, Clear, ListDirection, Padding, Paragraph, Scrollbar,
         ScrollbarOrientation,
@@ -11,15 +12,19 @@ use textwrap::Options;
 
 use crate::{
     app::{App, AppFocus},
-    models::Role,
+    conversations::SETTINGS_FIELDS,
+    models::{display_name, Role},
 };
 
 const FOCUS_BORDER_TYPE: BorderType = BorderType::Double;
 const NORMAL_BORDER_TYPE: BorderType = BorderType::Rounded;
 
 pub fn render(app: &mut App, frame: &mut Frame) {
-    let dimmed =
-        app.new_conversation_popup.is_activated() | app.delete_conversation_popup.is_activated();
+    let dimmed = app.new_conversation_popup.is_activated()
+        | app.delete_conversation_popup.is_activated()
+        | app.conversation_settings_popup.is_activated()
+        | app.search_popup.is_activated()
+        | app.related_conversations_popup.is_activated();
     let color = match dimmed {
         true => Color::DarkGray,
         false => Color::White,
@@ -35,11 +40,24 @@ pub fn render(app: &mut App, frame: &mut Frame) {
     // conversations widget
     let conversations = app
         .conversations
-        .as_list_widget(|conversation| conversation.name.trim().to_owned())
+        .as_list_widget(|conversation| {
+            let name = display_name(&conversation.name);
+            match conversation.deleted_at {
+                Some(deleted_at) => format!("{} ({})", name, deleted_at.format("%Y-%m-%d")),
+                None => name.to_owned(),
+            }
+        })
         .style(color)
         .block(
             Block::bordered()
-                .title("CONVERSATIONS")
+                .title(match app.conversations.is_showing_trash() {
+                    true => "TRASH",
+                    false => "CONVERSATIONS",
+                })
+                .title_bottom(match app.conversations.is_showing_trash() {
+                    true => "<u> restore, <t> back",
+                    false => "<t> trash",
+                })
                 .title_alignment(Alignment::Left)
                 .border_type(match app.current_focus() {
                     AppFocus::Conversation => FOCUS_BORDER_TYPE,
@@ -63,11 +81,14 @@ pub fn render(app: &mut App, frame: &mut Frame) {
         ])
         .split(chunks[1]);
     let message_padding = Padding::new(1, 1, 0, 0);
+    let selected_message_id = app.chat.selected_message().map(|message| message.id);
+    let summary_expanded = app.chat.summary_expanded();
+    let errors = app.chat.errors().clone();
 
     let messages = app
         .chat
         .as_paragraph(
-            |message| {
+            |message, branch, in_context| {
                 let width = messages_layout[0].width
                     - 3
                     - (message_padding.left + message_padding.right) * 2;
@@ -78,8 +99,33 @@ pub fn render(app: &mut App, frame: &mut Frame) {
                 };
                 let content =
                     textwrap::wrap(message.content.trim(), Options::new(width as usize)).join("\n");
+                if message.summary {
+                    if summary_expanded {
+                        return format!("📝 {content}");
+                    }
+                    return "📝 Summary of earlier messages, press <s> to expand".to_string();
+                }
+
+                let mut text = match branch {
+                    Some(branch) => {
+                        format!("{icon} < {}/{} > {content}", branch.position, branch.count)
+                    }
+                    None => format!("{icon} {content}"),
+                };
+                if message.interrupted {
+                    text.push_str(" [interrupted]");
+                }
+                if let Some(error) = errors.get(&message.id) {
+                    text.push_str(&format!(" [error: {error}]"));
+                }
+                if !in_context {
+                    text.push_str(" [out of context]");
+                }
+                if selected_message_id == Some(message.id) {
+                    text = format!("▶ {text}");
+                }
 
-                format!("{icon} {content}")
+                text
             },
             messages_layout[0].height as usize,
         )
@@ -87,6 +133,23 @@ pub fn render(app: &mut App, frame: &mut Frame) {
             Block::bordered()
                 .title("CHAT")
                 .title_alignment(Alignment::Left)
+                .title_top(
+                    Line::from(format!(
+                        "{}: {}",
+                        app.model_info().backend,
+                        app.model_info().name
+                    ))
+                    .right_aligned(),
+                )
+                .title_bottom(
+                    Line::from(match app.chat.find_status() {
+                        Some((current, total)) => {
+                            format!("match {current}/{total}, <n>/<N> next/previous")
+                        }
+                        None => String::new(),
+                    })
+                    .right_aligned(),
+                )
                 .border_type(match app.current_focus() {
                     AppFocus::Messages => FOCUS_BORDER_TYPE,
                     _ => NORMAL_BORDER_TYPE,
@@ -106,6 +169,30 @@ pub fn render(app: &mut App, frame: &mut Frame) {
         &mut app.chat.vertical_scrollbar_state,
     );
 
+    if app.find_bar.is_activated() {
+        let chat_area = messages_layout[0];
+        let find_bar_height = 3.min(chat_area.height);
+        let find_bar_area = Rect::new(
+            chat_area.x,
+            chat_area.y + chat_area.height - find_bar_height,
+            chat_area.width,
+            find_bar_height,
+        );
+        frame.render_widget(Clear, find_bar_area);
+
+        let text_area = app.find_bar.text_area_mut();
+        text_area.set_block(
+            Block::bordered()
+                .title("FIND")
+                .title_alignment(Alignment::Left)
+                .title_bottom(Line::from("<Enter> keep matches, <Esc> cancel").right_aligned())
+                .border_type(FOCUS_BORDER_TYPE)
+                .style(Color::White),
+        );
+        text_area.set_cursor_style(Style::default().reversed());
+        frame.render_widget(&*app.find_bar, find_bar_area);
+    }
+
     // TODO: I need to put text to new line when it reaches width of the block
     // TODO: set prompt to different colors depending on the state of LLM response
     // 1 - green - prompt in a good shape (non empty string)
@@ -125,27 +212,61 @@ pub fn render(app: &mut App, frame: &mut Frame) {
     frame.render_widget(&*app.prompt, messages_layout[1]);
 
     if app.new_conversation_popup.is_activated() {
-        let (popup_width, popup_height) = (50, 3);
+        let (popup_width, popup_height) = (50, 6);
         let (popup_x, popup_y) =
             calculate_coordinates((area.width, area.height), (popup_width, popup_height));
         let popup_area = Rect::new(popup_x, popup_y, popup_width, popup_height);
         frame.render_widget(Clear, popup_area);
+        let popup_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(3), Constraint::Length(3)])
+            .split(popup_area);
+        let system_prompt_selected = app.new_conversation_popup.is_system_prompt_selected();
 
         app.new_conversation_popup.set_block(
             Block::new()
                 .borders(Borders::ALL)
-                .border_type(BorderType::Rounded)
+                .border_type(match system_prompt_selected {
+                    true => NORMAL_BORDER_TYPE,
+                    false => FOCUS_BORDER_TYPE,
+                })
                 .style(Color::White),
         );
         app.new_conversation_popup
             .set_placeholder_style(Style::default());
         app.new_conversation_popup
-            .set_placeholder_text("Give a name to your new conversation");
-        frame.render_widget(&*app.new_conversation_popup, popup_area);
+            .set_placeholder_text("Give a name to your new conversation or leave it empty");
+        app.new_conversation_popup
+            .set_cursor_style(match system_prompt_selected {
+                true => Style::default(),
+                false => Style::default().reversed(),
+            });
+        frame.render_widget(&*app.new_conversation_popup, popup_chunks[0]);
+
+        let system_prompt = app.new_conversation_popup.system_prompt_text_area_mut();
+        system_prompt.set_block(
+            Block::new()
+                .borders(Borders::ALL)
+                .border_type(match system_prompt_selected {
+                    true => FOCUS_BORDER_TYPE,
+                    false => NORMAL_BORDER_TYPE,
+                })
+                .style(Color::White),
+        );
+        system_prompt.set_placeholder_style(Style::default());
+        system_prompt.set_placeholder_text("System prompt (optional, <Tab> to edit)");
+        system_prompt.set_cursor_style(match system_prompt_selected {
+            true => Style::default().reversed(),
+            false => Style::default(),
+        });
+        frame.render_widget(&*system_prompt, popup_chunks[1]);
     }
 
     if app.delete_conversation_popup.is_activated() {
-        let popup_message = "Would you like to delete conversation? <Y/n>";
+        let popup_message = match app.conversations.is_showing_trash() {
+            true => "Delete conversation permanently? <Y/n>",
+            false => "Move conversation to trash? <Y/n>",
+        };
         let (popup_width, popup_height) = (50, 3);
         let (popup_x, popup_y) =
             calculate_coordinates((area.width, area.height), (popup_width, popup_height));
@@ -159,6 +280,205 @@ pub fn render(app: &mut App, frame: &mut Frame) {
             .style(Color::White);
         frame.render_widget(paragraph, popup_area);
     }
+
+    if app.conversation_settings_popup.is_activated() {
+        render_conversation_settings_popup(app, frame, area);
+    }
+
+    if app.edit_message_popup.is_activated() {
+        let (popup_width, popup_height) = (60.min(area.width), 10.min(area.height));
+        let (popup_x, popup_y) =
+            calculate_coordinates((area.width, area.height), (popup_width, popup_height));
+        let popup_area = Rect::new(popup_x, popup_y, popup_width, popup_height);
+        frame.render_widget(Clear, popup_area);
+
+        let text_area = app.edit_message_popup.text_area_mut();
+        text_area.set_block(
+            Block::bordered()
+                .title("EDIT MESSAGE")
+                .title_alignment(Alignment::Left)
+                .title_bottom(Line::from("<Enter> re-run, <Esc> cancel").right_aligned())
+                .border_type(FOCUS_BORDER_TYPE)
+                .style(Color::White),
+        );
+        text_area.set_cursor_style(Style::default().reversed());
+        frame.render_widget(&*app.edit_message_popup, popup_area);
+    }
+
+    if app.rename_conversation_popup.is_activated() {
+        let (popup_width, popup_height) = (50, 3);
+        let (popup_x, popup_y) =
+            calculate_coordinates((area.width, area.height), (popup_width, popup_height));
+        let popup_area = Rect::new(popup_x, popup_y, popup_width, popup_height);
+        frame.render_widget(Clear, popup_area);
+
+        let text_area = app.rename_conversation_popup.text_area_mut();
+        text_area.set_block(
+            Block::bordered()
+                .title("RENAME CONVERSATION")
+                .title_alignment(Alignment::Left)
+                .title_bottom(Line::from("<Enter> save, <Esc> cancel").right_aligned())
+                .border_type(FOCUS_BORDER_TYPE)
+                .style(Color::White),
+        );
+        text_area.set_cursor_style(Style::default().reversed());
+        frame.render_widget(&*app.rename_conversation_popup, popup_area);
+    }
+
+    if app.export_conversation_popup.is_activated() {
+        let (popup_width, popup_height) = (70.min(area.width), 3);
+        let (popup_x, popup_y) =
+            calculate_coordinates((area.width, area.height), (popup_width, popup_height));
+        let popup_area = Rect::new(popup_x, popup_y, popup_width, popup_height);
+        frame.render_widget(Clear, popup_area);
+
+        let bottom = match app.export_conversation_popup.status() {
+            Some(status) => status.clone(),
+            None => format!(
+                "<Tab> format: {}, <Enter> export, <Esc> cancel",
+                app.export_conversation_popup.format()
+            ),
+        };
+        let text_area = app.export_conversation_popup.text_area_mut();
+        text_area.set_block(
+            Block::bordered()
+                .title("EXPORT CONVERSATION TO DIRECTORY")
+                .title_alignment(Alignment::Left)
+                .title_bottom(Line::from(bottom).right_aligned())
+                .border_type(FOCUS_BORDER_TYPE)
+                .style(Color::White),
+        );
+        text_area.set_cursor_style(Style::default().reversed());
+        frame.render_widget(&*app.export_conversation_popup, popup_area);
+    }
+
+    if app.search_popup.is_activated() {
+        render_search_popup(app, frame, area);
+    }
+
+    if app.related_conversations_popup.is_activated() {
+        let (popup_width, popup_height) = (60.min(area.width), 14.min(area.height));
+        let (popup_x, popup_y) =
+            calculate_coordinates((area.width, area.height), (popup_width, popup_height));
+        let popup_area = Rect::new(popup_x, popup_y, popup_width, popup_height);
+        frame.render_widget(Clear, popup_area);
+
+        let related = app
+            .related_conversations_popup
+            .as_list_widget(|related| {
+                let name = display_name(&related.conversation.name);
+                format!("{name} ({:.0}%)", related.similarity.max(0.0) * 100.0)
+            })
+            .style(Color::White)
+            .block(
+                Block::bordered()
+                    .title("RELATED CONVERSATIONS")
+                    .title_alignment(Alignment::Left)
+                    .title_bottom(Line::from("<Enter> open, <Esc> cancel").right_aligned())
+                    .border_type(FOCUS_BORDER_TYPE)
+                    .padding(Padding::new(1, 1, 0, 0)),
+            )
+            .highlight_style(Style::default().bold())
+            .highlight_symbol("👉 ")
+            .repeat_highlight_symbol(false)
+            .direction(ListDirection::TopToBottom);
+        frame.render_stateful_widget(
+            related,
+            popup_area,
+            &mut app.related_conversations_popup.state,
+        );
+    }
+}
+
+fn render_search_popup(app: &mut App, frame: &mut Frame, area: Rect) {
+    let (popup_width, popup_height) = (80.min(area.width), 20.min(area.height));
+    let (popup_x, popup_y) =
+        calculate_coordinates((area.width, area.height), (popup_width, popup_height));
+    let popup_area = Rect::new(popup_x, popup_y, popup_width, popup_height);
+    frame.render_widget(Clear, popup_area);
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Length(3), Constraint::Min(0)])
+        .split(popup_area);
+
+    let text_area = app.search_popup.text_area_mut();
+    text_area.set_block(
+        Block::bordered()
+            .title("SEARCH MESSAGES")
+            .title_alignment(Alignment::Left)
+            .border_type(FOCUS_BORDER_TYPE)
+            .style(Color::White),
+    );
+    text_area.set_cursor_style(Style::default().reversed());
+    frame.render_widget(&*app.search_popup, popup_chunks[0]);
+
+    let hits = app
+        .search_popup
+        .as_list_widget(|hit| {
+            let conversation_name = display_name(&hit.conversation_name);
+            format!("{conversation_name}: {}", hit.snippet.replace('\n', " "))
+        })
+        .style(Color::White)
+        .block(
+            Block::bordered()
+                .title_bottom(Line::from("<Enter> open, <Esc> cancel").right_aligned())
+                .border_type(NORMAL_BORDER_TYPE)
+                .padding(Padding::new(1, 1, 0, 0)),
+        )
+        .highlight_style(Style::default().bold())
+        .highlight_symbol("👉 ")
+        .repeat_highlight_symbol(false)
+        .direction(ListDirection::TopToBottom);
+    frame.render_stateful_widget(hits, popup_chunks[1], &mut app.search_popup.state);
+}
+
+fn render_conversation_settings_popup(app: &mut App, frame: &mut Frame, area: Rect) {
+    let (popup_width, popup_height) = (
+        50.min(area.width),
+        (3 * SETTINGS_FIELDS.len() as u16 + 3).min(area.height),
+    );
+    let (popup_x, popup_y) =
+        calculate_coordinates((area.width, area.height), (popup_width, popup_height));
+    let popup_area = Rect::new(popup_x, popup_y, popup_width, popup_height);
+    frame.render_widget(Clear, popup_area);
+
+    let block = Block::bordered()
+        .title("CONVERSATION SETTINGS")
+        .title_alignment(Alignment::Left)
+        .border_type(BorderType::Rounded)
+        .style(Color::White);
+    let inner_area = block.inner(popup_area);
+    frame.render_widget(block, popup_area);
+
+    let mut constraints = vec![Constraint::Length(3); SETTINGS_FIELDS.len()];
+    constraints.push(Constraint::Length(1));
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(inner_area);
+
+    let error = app.conversation_settings_popup.error().cloned();
+    let selected = app.conversation_settings_popup.selected();
+    for (index, field) in app.conversation_settings_popup.fields_mut().enumerate() {
+        field.set_block(Block::bordered().title(SETTINGS_FIELDS[index]).border_type(
+            match index == selected {
+                true => FOCUS_BORDER_TYPE,
+                false => NORMAL_BORDER_TYPE,
+            },
+        ));
+        field.set_cursor_style(match index == selected {
+            true => Style::default().reversed(),
+            false => Style::default(),
+        });
+        field.set_placeholder_text("default");
+        frame.render_widget(&*field, chunks[index]);
+    }
+
+    let footer = match error {
+        Some(error) => Paragraph::new(error).style(Color::Red),
+        None => Paragraph::new("<Enter> save, <Esc> cancel").style(Color::DarkGray),
+    };
+    frame.render_widget(footer, chunks[SETTINGS_FIELDS.len()]);
 }
 
 fn calculate_coordinates(area_size: (u16, u16), elem_size: (u16, u16)) -> (u16, u16) {